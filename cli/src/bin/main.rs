@@ -1,23 +1,406 @@
-use cli::{CliConfig, Cli};
-use translator::yaml;
+use cli::{CliConfig, Cli, OutputMode};
+use serde_json::{Map, Value};
+use translator::Tree;
+use std::env;
 use std::fs;
-use yaml_rust::YamlLoader;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+use yaml_rust::{Yaml, YamlLoader};
+
+const DEFAULT_TRANSLATIONS: &str = "translator/translations.yml";
+const TRANSLATIONS_ENV: &str = "GS_CLI_TRANSLATIONS";
+
+const DEFAULT_CONFIG: &str = "gs-cli.yml";
+const CONFIG_ENV: &str = "GS_CLI_CONFIG";
+
+const DEFAULT_PROMPT: &str = "$: ";
+
+/// Where history is kept, in the home directory, unless the config says otherwise.
+const DEFAULT_HISTORY_FILE: &str = ".gs-cli-history";
+
+const FORMATS: &str = ".yml, .yaml, .json or .toml";
+
+const USAGE: &str = "Usage: main [--translations <path>] [--config <path>] [--profile <name>] [--json] [--version] [script]";
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+struct Args {
+    translations: String,
+    /// None when no config was asked for, in which case `DEFAULT_CONFIG` is
+    /// read if it's there.
+    config: Option<String>,
+    profile: Option<String>,
+    json: bool,
+    version: bool,
+    script: Option<String>,
+}
+
+// --translations and --config win over the environment, which wins over the default
+fn parse_args() -> Result<Args, String> {
+    let mut translations = env::var(TRANSLATIONS_ENV).ok();
+    let mut config = env::var(CONFIG_ENV).ok();
+    let mut profile = None;
+    let mut json = false;
+    let mut version = false;
+    let mut script = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--translations" => match args.next() {
+                Some(path) => translations = Some(path),
+                None => return Err("--translations needs a path".to_string()),
+            },
+            "--config" => match args.next() {
+                Some(path) => config = Some(path),
+                None => return Err("--config needs a path".to_string()),
+            },
+            "--profile" => match args.next() {
+                Some(name) => profile = Some(name),
+                None => return Err("--profile needs a name".to_string()),
+            },
+            "--json" => json = true,
+            "--version" => version = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if script.is_none() => script = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(Args {
+        translations: translations.unwrap_or_else(|| DEFAULT_TRANSLATIONS.to_string()),
+        config,
+        profile,
+        json,
+        version,
+        script,
+    })
+}
+
+/// What was loaded at startup, shown before the first prompt.
+struct Banner<'p> {
+    translations: &'p str,
+    commands: usize,
+}
+
+impl std::fmt::Display for Banner<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "gs-cli {}", VERSION)?;
+        write!(f, "Loaded {} commands from {}", self.commands, self.translations)
+    }
+}
+
+/// A parsed translations file. Trees borrow from it, so it has to outlive them.
+enum Document {
+    Yaml(Vec<Yaml>),
+    Json(serde_json::Value),
+    Toml(toml::Value),
+}
+
+impl Document {
+    /// Parses `text` in the format `path`'s extension names.
+    fn parse(path: &str, text: &str) -> Result<Document, String> {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+
+        match extension {
+            Some("yml") | Some("yaml") => YamlLoader::load_from_str(text)
+                .map(Document::Yaml)
+                .map_err(|e| e.to_string()),
+            Some("json") => serde_json::from_str(text)
+                .map(Document::Json)
+                .map_err(|e| e.to_string()),
+            Some("toml") => text
+                .parse()
+                .map(Document::Toml)
+                .map_err(|e: toml::de::Error| e.message().to_string()),
+            _ => Err(format!("unsupported format, expected {}", FORMATS)),
+        }
+    }
+
+    /// The first document as JSON, so that settings read the same whatever
+    /// the format.
+    fn to_json(&self) -> Result<Value, String> {
+        match self {
+            Document::Yaml(docs) => match docs.first() {
+                Some(doc) => yaml_to_json(doc),
+                None => Err("empty document".to_string()),
+            },
+            Document::Json(value) => Ok(value.clone()),
+            Document::Toml(value) => serde_json::to_value(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Builds the tree of the first document, or of the named profile.
+    fn to_tree(&self, profile: Option<&str>) -> Result<Tree<'_>, String> {
+        match (self, profile) {
+            (Document::Yaml(docs), Some(profile)) => {
+                translator::yaml::profile_to_tree(docs, profile).map_err(|e| e.to_string())
+            }
+            (Document::Yaml(docs), None) => match docs.first() {
+                Some(doc) => translator::yaml::to_tree(doc).map_err(|e| e.to_string()),
+                None => Err("empty document".to_string()),
+            },
+            (_, Some(_)) => Err("profiles are only supported in YAML files".to_string()),
+            (Document::Json(value), None) => translator::json::to_tree(value).map_err(|e| e.to_string()),
+            (Document::Toml(value), None) => translator::toml::to_tree(value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn yaml_to_json(yaml: &Yaml) -> Result<Value, String> {
+    let value = match yaml {
+        Yaml::String(s) => Value::from(s.as_str()),
+        Yaml::Integer(i) => Value::from(*i),
+        Yaml::Real(_) => yaml.as_f64().map_or(Value::Null, Value::from),
+        Yaml::Boolean(b) => Value::from(*b),
+        Yaml::Array(items) => Value::Array(items.iter().map(yaml_to_json).collect::<Result<_, _>>()?),
+        Yaml::Hash(hash) => {
+            let mut map = Map::new();
+            for (key, val) in hash {
+                let key = key.as_str().ok_or("setting names must be strings")?;
+                map.insert(key.to_string(), yaml_to_json(val)?);
+            }
+            Value::Object(map)
+        }
+        _ => Value::Null,
+    };
+
+    Ok(value)
+}
+
+/// What a config file can set, each overriding `CliConfig`'s default:
+///
+/// ```yaml
+/// prompt: "gs> "
+/// prompt_format: "gs[{path}]> "
+/// exit: [exit, quit, q]
+/// color: false
+/// delimiter: " "
+/// path_separator: ":"
+/// history_file: .gs-history
+/// history_size: 500
+/// state_file: .gs-state
+/// case_insensitive: true
+/// timeout: 2.5
+/// dry_run: true
+/// ```
+///
+/// Any of them may be left out. Unknown keys are an error, so that a typo
+/// doesn't go unnoticed. Without a `history_file`, history is kept in
+/// `DEFAULT_HISTORY_FILE` in the home directory.
+#[derive(Default)]
+struct Settings {
+    prompt: Option<String>,
+    prompt_format: Option<String>,
+    exit: Option<Vec<String>>,
+    color: Option<bool>,
+    delimiter: Option<char>,
+    path_separator: Option<char>,
+    history_file: Option<String>,
+    history_size: Option<usize>,
+    state_file: Option<String>,
+    case_insensitive: Option<bool>,
+    timeout: Option<Duration>,
+    dry_run: Option<bool>,
+}
+
+impl Settings {
+    /// Reads the settings at `path`, in any format a translations file can
+    /// have. Without an explicit `path`, a missing `DEFAULT_CONFIG` just
+    /// means the defaults.
+    fn load(path: Option<&str>) -> Result<Settings, String> {
+        let file = match path {
+            Some(path) => fs::read_to_string(path),
+            None if !Path::new(DEFAULT_CONFIG).exists() => return Ok(Settings::default()),
+            None => fs::read_to_string(DEFAULT_CONFIG),
+        };
+        let path = path.unwrap_or(DEFAULT_CONFIG);
+        let file = file.map_err(|e| format!("could not read config file: {}: {}", path, e))?;
+
+        let doc = Document::parse(path, &file)
+            .map_err(|e| format!("could not parse config file: {}: {}", path, e))?;
+
+        doc.to_json()
+            .and_then(|value| Settings::from_json(&value))
+            .map_err(|e| format!("Invalid config in {}: {}", path, e))
+    }
+
+    fn from_json(value: &Value) -> Result<Settings, String> {
+        let map = value.as_object().ok_or("expected a map of settings")?;
+        let mut settings = Settings::default();
+
+        for (key, val) in map {
+            match key.as_str() {
+                "prompt" => settings.prompt = Some(string(key, val)?),
+                "prompt_format" => settings.prompt_format = Some(string(key, val)?),
+                "exit" => settings.exit = Some(
+                    val.as_array()
+                        .ok_or_else(|| expected(key, "a list of commands"))?
+                        .iter()
+                        .map(|cmd| string(key, cmd))
+                        .collect::<Result<_, _>>()?
+                ),
+                "color" => settings.color = Some(boolean(key, val)?),
+                "delimiter" => settings.delimiter = Some(character(key, val)?),
+                "path_separator" => settings.path_separator = Some(character(key, val)?),
+                "history_file" => settings.history_file = Some(string(key, val)?),
+                "history_size" => settings.history_size = Some(
+                    val.as_u64()
+                        .and_then(|size| size.try_into().ok())
+                        .ok_or_else(|| expected(key, "a number of commands"))?
+                ),
+                "state_file" => settings.state_file = Some(string(key, val)?),
+                "case_insensitive" => settings.case_insensitive = Some(boolean(key, val)?),
+                "timeout" => settings.timeout = Some(
+                    val.as_f64()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| expected(key, "a timeout in seconds"))?
+                ),
+                "dry_run" => settings.dry_run = Some(boolean(key, val)?),
+                _ => return Err(format!("unknown setting '{}'", key)),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// A `CliConfig` for `tree` with these settings applied.
+    fn config<'a>(&'a self, tree: Tree<'a>) -> Result<CliConfig<'a>, String> {
+        let prompt = self.prompt.as_deref().unwrap_or(DEFAULT_PROMPT);
+        let mut config = CliConfig::new(prompt, tree).map_err(|e| e.to_string())?;
+
+        if let Some(template) = &self.prompt_format {
+            config = config.prompt_format(template);
+        }
+        if let Some(exit) = &self.exit {
+            config = config.exit_cmds(exit.iter().map(String::as_str).collect());
+        }
+        if let Some(color) = self.color {
+            config = config.color(color);
+        }
+        if let Some(delimiter) = self.delimiter {
+            config = config.delimiter(delimiter);
+        }
+        if let Some(separator) = self.path_separator {
+            config = config.path_separator(separator);
+        }
+        if let Some(path) = &self.history_file {
+            config = config.history_file(path);
+        }
+        if let Some(size) = self.history_size {
+            config = config.history_size(size);
+        }
+        if let Some(path) = &self.state_file {
+            config = config.state_file(path);
+        }
+        if let Some(enable) = self.case_insensitive {
+            config = config.case_insensitive(enable);
+        }
+        if let Some(timeout) = self.timeout {
+            config = config.timeout(timeout);
+        }
+        if let Some(enable) = self.dry_run {
+            config = config.dry_run(enable);
+        }
+
+        Ok(config)
+    }
+}
+
+fn default_history_file() -> Option<String> {
+    let home = env::var_os("HOME")?;
+    Path::new(&home).join(DEFAULT_HISTORY_FILE).into_os_string().into_string().ok()
+}
+
+fn expected(key: &str, what: &str) -> String {
+    format!("expected {} for '{}'", what, key)
+}
+
+fn string(key: &str, value: &Value) -> Result<String, String> {
+    value.as_str().map(str::to_string).ok_or_else(|| expected(key, "a string"))
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, String> {
+    value.as_bool().ok_or_else(|| expected(key, "true or false"))
+}
+
+fn character(key: &str, value: &Value) -> Result<char, String> {
+    let mut chars = value.as_str().unwrap_or_default().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(expected(key, "a single character")),
+    }
+}
+
+/// Reads and parses the translations at `path` and builds the tree. The
+/// tree owns its names rather than borrowing them from the document, so
+/// that `reload` can build a new one while the program runs.
+fn load(path: &str, profile: Option<&str>) -> Result<Tree<'static>, String> {
+    let file = fs::read_to_string(path)
+        .map_err(|e| format!("could not read translations file: {}: {}", path, e))?;
+
+    let doc = Document::parse(path, &file)
+        .map_err(|e| format!("could not parse translations file: {}: {}", path, e))?;
+
+    doc.to_tree(profile)
+        .map(Tree::into_owned)
+        .map_err(|e| format!("Invalid command tree in {}: {}", path, e))
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
 
 fn main() {
-    let file = fs::read_to_string("translator/translations.yml").expect("No such file.");
-    
-    let yaml = YamlLoader::load_from_str(&file).unwrap();
-    let cmd_tree = yaml::to_tree(&yaml[0]);
-    
+    let args = parse_args().unwrap_or_else(|e| fail(format!("{}\n{}", e, USAGE)));
+    if args.version {
+        println!("gs-cli {}", VERSION);
+        return;
+    }
+
+    let path = &args.translations;
+    let profile = args.profile.as_deref();
+
+    let mut settings = Settings::load(args.config.as_deref()).unwrap_or_else(|e| fail(e));
+    if settings.history_file.is_none() {
+        settings.history_file = default_history_file();
+    }
+    let cmd_tree = load(path, profile).unwrap_or_else(|e| fail(e));
+    let banner = Banner {
+        translations: path,
+        commands: cmd_tree.subtree_count(&cmd_tree.root),
+    };
 
-    let config = CliConfig::new(
-        "$: ", 
-        cmd_tree,
-    ).expect("Invalid configuration");
+    let config = settings
+        .config(cmd_tree)
+        .unwrap_or_else(|e| fail(e))
+        .reload_with(|| load(path, profile))
+        .catch_interrupt(true);
+
+    let config = if args.json {
+        config.output_mode(OutputMode::Json)
+    } else {
+        config
+    };
 
     let mut cli = Cli::open(config);
 
-    cli.run();
+    // A script argument runs its commands and exits instead of prompting
+    if let Some(script) = &args.script {
+        if let Err(e) = cli.run_script(Path::new(script)) {
+            fail(format!("{}: {}", script, e));
+        }
+        return;
+    }
+
+    // Alongside the prompts, so that stdout only carries command output
+    eprintln!("{}", banner);
+
+    if let Err(e) = cli.run() {
+        fail(e.to_string());
+    }
 
     println!("Thanks for coming :)");
 }
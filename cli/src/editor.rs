@@ -0,0 +1,226 @@
+use std::io::{self, BufRead};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Key {
+    Char(char),
+    Tab,
+    Enter,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    DeleteWord,
+    DeleteLine,
+    Interrupt,
+    Eof,
+    Other,
+}
+
+pub(crate) fn read_key<R: BufRead>(reader: &mut R) -> io::Result<Key> {
+    let byte = match read_byte(reader)? {
+        Some(b) => b,
+        None => return Ok(Key::Eof),
+    };
+
+    let key = match byte {
+        b'\t' => Key::Tab,
+        b'\n' => Key::Enter,
+        b'\r' => {
+            // A CRLF line ending is a single Enter
+            if reader.fill_buf()?.first() == Some(&b'\n') {
+                reader.consume(1);
+            }
+            Key::Enter
+        }
+        0x08 | 0x7f => Key::Backspace,
+        0x01 => Key::Home,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x15 => Key::DeleteLine,
+        0x17 => Key::DeleteWord,
+        0x1b => read_escape(reader)?,
+        b if b.is_ascii() => Key::Char(b as char),
+        b => {
+            // Multi-byte UTF-8; the leading byte tells us how many follow
+            let len = if b >= 0xf0 { 4 } else if b >= 0xe0 { 3 } else { 2 };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                match read_byte(reader)? {
+                    Some(b) => bytes.push(b),
+                    None => break,
+                }
+            }
+            Key::Char(
+                String::from_utf8_lossy(&bytes).chars().next().unwrap_or(char::REPLACEMENT_CHARACTER)
+            )
+        }
+    };
+
+    Ok(key)
+}
+
+fn read_escape<R: BufRead>(reader: &mut R) -> io::Result<Key> {
+    // Arrow keys arrive as ESC [ A or, in application mode, ESC O A, and so
+    // do Home and End as ESC [ H and ESC [ F
+    match read_byte(reader)? {
+        Some(b'[') | Some(b'O') => {}
+        _ => return Ok(Key::Other),
+    }
+
+    let key = match read_byte(reader)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        _ => Key::Other,
+    };
+
+    Ok(key)
+}
+
+fn read_byte<R: BufRead>(reader: &mut R) -> io::Result<Option<u8>> {
+    let byte = match reader.fill_buf()?.first() {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+    reader.consume(1);
+    Ok(Some(byte))
+}
+
+pub(crate) fn common_prefix<'a>(words: &[&'a str]) -> &'a str {
+    let first = match words.first() {
+        Some(w) => *w,
+        None => return "",
+    };
+
+    let mut len = first.len();
+    for word in &words[1..] {
+        len = first
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, c1), c2)| c1 == c2)
+            .map(|((i, c), _)| i + c.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+
+    &first[..len]
+}
+
+/// Where the word before `end` starts in `line`, skipping spaces between
+/// the two, for deleting a word at a time.
+pub(crate) fn word_start(line: &str, end: usize) -> usize {
+    let before = line[..end].trim_end_matches(' ');
+    before.rfind(' ').map_or(0, |i| i + 1)
+}
+
+/// Puts the terminal in non-canonical mode without echo for as long as it lives,
+/// so that keys such as Tab reach the editor before Enter is pressed. With
+/// `catch_interrupt`, Ctrl-C reaches it too rather than raising SIGINT.
+#[cfg(feature = "interactive")]
+pub(crate) struct RawMode {
+    #[cfg(unix)]
+    original: termios::Termios,
+}
+
+#[cfg(feature = "interactive")]
+impl RawMode {
+    #[cfg(unix)]
+    pub(crate) fn enable(catch_interrupt: bool) -> Option<RawMode> {
+        use std::io::IsTerminal;
+        use termios::{tcsetattr, Termios, ECHO, ICANON, ISIG, TCSANOW};
+
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return None;
+        }
+
+        let original = Termios::from_fd(0).ok()?;
+        let mut raw = original;
+        raw.c_lflag &= !(ICANON | ECHO);
+        if catch_interrupt {
+            raw.c_lflag &= !ISIG;
+        }
+        tcsetattr(0, TCSANOW, &raw).ok()?;
+
+        Some(RawMode { original })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn enable(_catch_interrupt: bool) -> Option<RawMode> {
+        None
+    }
+}
+
+#[cfg(feature = "interactive")]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = termios::tcsetattr(0, termios::TCSANOW, &self.original);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn keys() {
+        let mut input = Cursor::new("a\tø\x7f\x1b[A\x1bOB\x1b[Z\n".as_bytes());
+        assert_eq!(read_key(&mut input).unwrap(), Key::Char('a'));
+        assert_eq!(read_key(&mut input).unwrap(), Key::Tab);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Char('ø'));
+        assert_eq!(read_key(&mut input).unwrap(), Key::Backspace);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Up);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Down);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Other);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Enter);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Eof);
+
+        let mut input = Cursor::new("\r\n\r\r".as_bytes());
+        assert_eq!(read_key(&mut input).unwrap(), Key::Enter);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Enter);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Enter);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Eof);
+    }
+
+    #[test]
+    fn editing_keys() {
+        let mut input = Cursor::new("\x1b[C\x1b[D\x1b[H\x1bOF\x01\x05\x17\x15\x03".as_bytes());
+        assert_eq!(read_key(&mut input).unwrap(), Key::Right);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Left);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Home);
+        assert_eq!(read_key(&mut input).unwrap(), Key::End);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Home);
+        assert_eq!(read_key(&mut input).unwrap(), Key::End);
+        assert_eq!(read_key(&mut input).unwrap(), Key::DeleteWord);
+        assert_eq!(read_key(&mut input).unwrap(), Key::DeleteLine);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Interrupt);
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(word_start("sat obc ping", 12), 8);
+        assert_eq!(word_start("sat obc  ", 9), 4);
+        assert_eq!(word_start("sat obc ping", 7), 4);
+        assert_eq!(word_start("sat", 3), 0);
+        assert_eq!(word_start("", 0), 0);
+    }
+
+    #[test]
+    fn prefix() {
+        assert_eq!(common_prefix(&[]), "");
+        assert_eq!(common_prefix(&["ping"]), "ping");
+        assert_eq!(common_prefix(&["set", "set_freq"]), "set");
+        assert_eq!(common_prefix(&["get_status", "get_state"]), "get_stat");
+        assert_eq!(common_prefix(&["ping", "take_pic"]), "");
+    }
+}
@@ -1,14 +1,181 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{self, OpenOptions};
 use std::io::{self, BufRead, Write};
-use translator::{Tree, Node, NodeId, Depth};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use translator::{Tree, TreeRef, Node, NodeId, Depth};
+pub use translator;
+use editor::Key;
+
+mod editor;
+#[cfg(feature = "rustyline")]
+mod readline;
 
 #[derive(Debug)]
 pub enum CliError<'a> {
     InvalidConfig(&'a str),
+    InvalidPath(&'a str),
+    /// A path segment that starts the names of several commands, given here.
+    AmbiguousPath { path: &'a str, candidates: Vec<String> },
+    /// A path to a command that can be run, which `cd` can't go into.
+    NotAGroup(&'a str),
+    Io(io::Error),
+    /// A script line that was not accepted, numbered from 1.
+    Rejected { line: usize, input: String },
+}
+
+impl<'a> From<io::Error> for CliError<'a> {
+    fn from(e: io::Error) -> CliError<'a> {
+        CliError::Io(e)
+    }
+}
+
+impl<'a> fmt::Display for CliError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::InvalidConfig(reason) => write!(f, "Invalid configuration: {}", reason),
+            CliError::InvalidPath(path) => write!(f, "No such path: {}", path),
+            CliError::AmbiguousPath { path, candidates } => {
+                write!(f, "Ambiguous path: {}\n{}", path, candidates.join("  "))
+            }
+            CliError::NotAGroup(path) => write!(f, "Not a command group: {}", path),
+            CliError::Io(e) => write!(f, "I/O error: {}", e),
+            CliError::Rejected { line, input } => write!(f, "Line {} was not accepted: {}", line, input),
+        }
+    }
+}
+
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// How many places `back` can return through.
+const MAX_VISITED: usize = 100;
+
+/// Node name that matches any single command word.
+const WILDCARD: &str = "*";
+
+/// Stands for the current path in `CliConfig::prompt_format`.
+const PATH_PLACEHOLDER: &str = "{path}";
+
+const CYAN: &str = "\x1b[36m";
+const GRAY: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// Why `Cli::handle_input` didn't accept some input, with what a caller needs
+/// to explain the valid usage.
+#[derive(Debug)]
+pub struct UsageError<'c> {
+    /// The commands matched before the input ran out or stopped matching.
+    pub matched: Vec<Node<'c>>,
+    /// The deepest command matched, or the current root if none was.
+    pub last_valid_node: NodeId,
+    /// The listed commands that could have come next.
+    pub expected: Vec<Node<'c>>,
+    /// The first word that didn't match, unless the input just ended early.
+    pub unmatched: Option<Cow<'c, str>>,
+}
+
+impl UsageError<'_> {
+    /// Whether every word matched and the input only needs more of them, as
+    /// opposed to having a word that matched nothing.
+    pub fn is_incomplete(&self) -> bool {
+        self.unmatched.is_none()
+    }
+}
+
+/// Why `Cli::handle_input` rejected some input.
+#[derive(Debug)]
+pub enum InputError<'c> {
+    /// A `"` was never closed. Holds the input from that quote on.
+    UnterminatedQuote(&'c str),
+    /// The words didn't make up a command.
+    Usage(UsageError<'c>),
+}
+
+impl fmt::Display for InputError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::UnterminatedQuote(rest) => write!(f, "Unterminated quote: {}", rest),
+            InputError::Usage(_) => write!(f, "Not a valid command"),
+        }
+    }
+}
+
+/// What `Cli::validate` made of a command line.
+#[derive(Debug, PartialEq)]
+pub enum ValidationResult<'c> {
+    /// A complete command, as `Cli::handle_input` accepts it.
+    Accepted(Vec<Node<'c>>),
+    /// Every word matched, but the line stopped short of a command.
+    Incomplete {
+        /// The deepest command matched, or the current root if none was.
+        last_valid: NodeId,
+        /// The listed commands that could come next.
+        options: Vec<Node<'c>>,
+    },
+    /// A word matched nothing, or the line couldn't be split into words.
+    Unknown,
+}
+
+/// How accepted commands are reported.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputMode {
+    /// An `ACCEPTED` line, see `CliConfig::print_accepted`.
+    Human,
+    /// A one-line JSON record such as
+    /// `{"path":["sat","obc","ping"],"status":"accepted"}`.
+    Json,
 }
 
+/// Follows a session as it happens, for front-ends that would rather not
+/// parse what the cli prints. Printing goes on as usual. Every method does
+/// nothing unless overridden.
+pub trait CliObserver {
+    /// The current path changed, to `path` as the prompt shows it.
+    fn on_prompt_change(&mut self, _path: &str) {}
+
+    /// A command was accepted, named by the words that matched it.
+    fn on_accept(&mut self, _path: &[&str]) {}
+
+    /// A line was not a command. `options` are the commands that could have
+    /// followed `last_valid`, the deepest one matched.
+    fn on_usage(&mut self, _last_valid: NodeId, _options: &[Node]) {}
+}
+
+type AcceptHandler<'a> = Box<dyn FnMut(&[&str], Option<Duration>) + 'a>;
+type CommandHandler<'a> = Box<dyn FnMut(&[&str]) + 'a>;
+type Reloader<'a> = Box<dyn FnMut() -> Result<Tree<'a>, String> + 'a>;
+
 pub struct CliConfig<'a> {
     prompt: &'a str,
     valid_cmds: Tree<'a>,
+    history_file: Option<&'a str>,
+    state_file: Option<&'a str>,
+    history_size: usize,
+    exit_cmds: Vec<&'a str>,
+    color: bool,
+    on_accept: Option<AcceptHandler<'a>>,
+    dispatcher: Option<Dispatcher>,
+    /// Handlers for single commands, by path such as `sat/obc/ping`.
+    commands: Vec<(&'a str, CommandHandler<'a>)>,
+    observer: Option<Box<dyn CliObserver + 'a>>,
+    reloader: Option<Reloader<'a>>,
+    timeout: Option<Duration>,
+    print_accepted: bool,
+    verbose: bool,
+    case_insensitive: bool,
+    delimiter: char,
+    path_separator: char,
+    stop_on_failure: bool,
+    dry_run: bool,
+    catch_interrupt: bool,
+    output_mode: OutputMode,
+    record_sink: Option<Box<dyn Write + 'a>>,
+    /// The prompt template split at each `{path}`.
+    prompt_format: Option<Vec<&'a str>>,
 }
 
 pub struct Cli<'a> {
@@ -16,17 +183,45 @@ pub struct Cli<'a> {
     current_prompt: String,
     current_root: NodeId,
     prev_root: Option<NodeId>,
+    /// Roots that `cd` moved away from, most recent last, for `back`.
+    visited: Vec<NodeId>,
+    echo: bool,
+    /// Whether input comes from a terminal, which shows what was typed.
+    interactive: bool,
+    color: bool,
+    history: Vec<String>,
+    new_history: usize,
+    /// Files being sourced, outermost first, so a file can't source itself.
+    sourcing: Vec<PathBuf>,
+    /// Shown when a session starts, for warnings from before there was one.
+    notice: Option<String>,
+    dry_run: bool,
 }
 
 #[derive(Debug, PartialEq)]
 struct CliCmd<'a> {
-    cmd: &'a str,
+    cmd: Cow<'a, str>,
     depth: Depth,
 }
 
+impl<'a> CliCmd<'a> {
+    /// Whether this is `node` typed by its name or one of its aliases.
+    fn matches(&self, node: &Node, case_insensitive: bool) -> bool {
+        let name_matches = node.names().any(|name| {
+            if case_insensitive {
+                self.cmd.eq_ignore_ascii_case(name)
+            } else {
+                self.cmd == name
+            }
+        });
+
+        name_matches && self.depth == node.depth
+    }
+}
+
 impl<'a> PartialEq<Node<'a>> for CliCmd<'a> {
     fn eq(&self, other: &Node) -> bool {
-        self.cmd == other.name && self.depth == other.depth
+        self.matches(other, false)
     }
 }
 
@@ -38,349 +233,3773 @@ impl<'a> CliConfig<'a> {
             Ok(CliConfig {
                 prompt,
                 valid_cmds,
+                history_file: None,
+                state_file: None,
+                history_size: DEFAULT_HISTORY_SIZE,
+                exit_cmds: vec!["exit", "quit"],
+                color: true,
+                on_accept: None,
+                dispatcher: None,
+                commands: Vec::new(),
+                observer: None,
+                reloader: None,
+                timeout: None,
+                print_accepted: true,
+                verbose: false,
+                case_insensitive: false,
+                delimiter: ' ',
+                path_separator: '/',
+                stop_on_failure: false,
+                dry_run: false,
+                catch_interrupt: false,
+                output_mode: OutputMode::Human,
+                record_sink: None,
+                prompt_format: None,
             })
         }
     }
+
+    /// Load history from `path` when the CLI opens and append to it on exit.
+    pub fn history_file(mut self, path: &'a str) -> CliConfig<'a> {
+        self.history_file = Some(path);
+        self
+    }
+
+    /// Reopen at the path saved in `path` when the CLI opens, and save the
+    /// current path there on exit.
+    pub fn state_file(mut self, path: &'a str) -> CliConfig<'a> {
+        self.state_file = Some(path);
+        self
+    }
+
+    /// Commands that end the session. End of input always does.
+    pub fn exit_cmds(mut self, cmds: Vec<&'a str>) -> CliConfig<'a> {
+        self.exit_cmds = cmds;
+        self
+    }
+
+    /// Colorize the prompt and command listings when built with the `color`
+    /// feature. Color is only ever used when stdout is a terminal.
+    pub fn color(mut self, enable: bool) -> CliConfig<'a> {
+        self.color = enable;
+        self
+    }
+
+    /// Called with the matched command path, e.g. `["sat", "obc", "ping"]`,
+    /// every time a command is accepted, along with how long it may take to
+    /// respond. The handler runs on the calling thread, so keeping to that is
+    /// up to it; use `dispatch_with` to have the CLI give up on it instead.
+    pub fn on_accept(mut self, handler: impl FnMut(&[&str], Option<Duration>) + 'a) -> CliConfig<'a> {
+        self.on_accept = Some(Box::new(handler));
+        self
+    }
+
+    /// Hands the matched path of every accepted command to `handler`, which
+    /// runs on a thread of its own, and waits for it to return for as long as
+    /// the command may take to respond. If it doesn't, the command is
+    /// reported as getting no response and the session goes on. Commands
+    /// accepted meanwhile wait for the handler to be done with that one.
+    pub fn dispatch_with(mut self, handler: impl FnMut(&[&str]) + Send + 'static) -> CliConfig<'a> {
+        self.dispatcher = Some(Dispatcher::spawn(handler));
+        self
+    }
+
+    /// Called instead of printing `ACCEPTED` whenever the command at `path`,
+    /// e.g. `sat/obc/ping`, is accepted, with the values given for its
+    /// arguments and those of the commands leading to it. `path` is separated
+    /// by the path separator and never holds argument values. `on_accept` is
+    /// still called for every command.
+    pub fn on_command(mut self, path: &'a str, handler: impl FnMut(&[&str]) + 'a) -> CliConfig<'a> {
+        self.commands.push((path, Box::new(handler)));
+        self
+    }
+
+    /// Tells `observer` about prompt changes, accepted commands and usage
+    /// errors as they happen.
+    pub fn observer(mut self, observer: impl CliObserver + 'a) -> CliConfig<'a> {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Lets the `reload` command swap in the tree `loader` returns, e.g. after
+    /// reading the translations file again. The tree borrows from `'a` like
+    /// the one given to `CliConfig::new`, and that borrow has to be valid
+    /// before `reload` returns, so the loader has to keep what it reads alive
+    /// on its own. Leaking it with `Box::leak` is simplest, at the price of
+    /// keeping every version in memory until the program exits.
+    pub fn reload_with(mut self, loader: impl FnMut() -> Result<Tree<'a>, String> + 'a) -> CliConfig<'a> {
+        self.reloader = Some(Box::new(loader));
+        self
+    }
+
+    /// How long accepted commands may take to respond, unless the command
+    /// tree sets a `timeout` for them. No limit by default. Enforced for
+    /// `dispatch_with`, and passed on to `on_accept`.
+    pub fn timeout(mut self, timeout: Duration) -> CliConfig<'a> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How to report accepted commands. `OutputMode::Human` by default.
+    pub fn output_mode(mut self, mode: OutputMode) -> CliConfig<'a> {
+        self.output_mode = mode;
+        self
+    }
+
+    /// Where `OutputMode::Json` records go instead of the session's output,
+    /// e.g. a log file.
+    pub fn record_sink(mut self, sink: impl Write + 'a) -> CliConfig<'a> {
+        self.record_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Make Ctrl-C throw away the line being typed instead of killing the
+    /// process. On an empty line it ends the session, like Ctrl-D. Off by
+    /// default.
+    pub fn catch_interrupt(mut self, enable: bool) -> CliConfig<'a> {
+        self.catch_interrupt = enable;
+        self
+    }
+
+    /// Print `ACCEPTED` when a command is accepted in `OutputMode::Human`.
+    /// On by default.
+    pub fn print_accepted(mut self, enable: bool) -> CliConfig<'a> {
+        self.print_accepted = enable;
+        self
+    }
+
+    /// Dump matching internals to stderr while handling input.
+    pub fn verbose(mut self, enable: bool) -> CliConfig<'a> {
+        self.verbose = enable;
+        self
+    }
+
+    /// Maximum number of commands kept in memory.
+    pub fn history_size(mut self, size: usize) -> CliConfig<'a> {
+        self.history_size = size;
+        self
+    }
+
+    /// Match commands regardless of ASCII case, so `SAT OBC PING` is accepted.
+    /// Prompts and paths keep the casing of the command tree. Off by default.
+    pub fn case_insensitive(mut self, enable: bool) -> CliConfig<'a> {
+        self.case_insensitive = enable;
+        self
+    }
+
+    /// Build the prompt from a template such as `"gs[{path}]> "`, where each
+    /// `{path}` becomes the current path, e.g. `sat/obc`. Without one the
+    /// prompt is the path followed by the prompt given to `CliConfig::new`.
+    pub fn prompt_format(mut self, template: &'a str) -> CliConfig<'a> {
+        self.prompt_format = Some(template.split(PATH_PLACEHOLDER).collect());
+        self
+    }
+
+    /// What separates the words of a command, e.g. `.` for `sat.obc.ping`.
+    /// A space by default. Paths given to `cd` and `ls` are still separated
+    /// by the path separator.
+    pub fn delimiter(mut self, delimiter: char) -> CliConfig<'a> {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// What separates the commands of a path, e.g. `:` for `cd sat:obc`, in
+    /// the prompt, `pwd`, `cd`, `ls` and commands run from the tree root.
+    /// A leading separator is the tree root. `/` by default.
+    pub fn path_separator(mut self, separator: char) -> CliConfig<'a> {
+        self.path_separator = separator;
+        self
+    }
+
+    /// Start sessions in dry-run mode, where accepted commands are reported
+    /// as what would run instead of being passed to `on_accept` or the
+    /// observer. `dryrun on` and `dryrun off` switch it during a session.
+    /// Off by default.
+    pub fn dry_run(mut self, enable: bool) -> CliConfig<'a> {
+        self.dry_run = enable;
+        self
+    }
+
+    /// Make `Cli::run_script` stop at the first line that isn't accepted
+    /// instead of running to the end of the file. Off by default.
+    pub fn stop_on_failure(mut self, enable: bool) -> CliConfig<'a> {
+        self.stop_on_failure = enable;
+        self
+    }
 }
 
 impl<'a> Cli<'a> {
     pub fn open(config: CliConfig) -> Cli {
         let root = config.valid_cmds.root;
-        Cli { 
-            config: config, 
+        let mut history = Vec::new();
+
+        // A missing history file just means nothing has been saved yet
+        if let Some(path) = config.history_file {
+            if let Ok(contents) = fs::read_to_string(path) {
+                history = contents
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(String::from)
+                    .collect();
+                // As in `add_history`, e.g. for files written before repeats were collapsed
+                history.dedup();
+            }
+        }
+
+        let dry_run = config.dry_run;
+        let mut cli = Cli { 
+            config, 
             current_prompt: String::new(), 
             current_root: root, 
-            prev_root: None 
-        }
+            prev_root: None,
+            visited: Vec::new(),
+            echo: false,
+            interactive: false,
+            color: false,
+            history,
+            new_history: 0,
+            sourcing: Vec::new(),
+            notice: None,
+            dry_run,
+        };
+        cli.truncate_history();
+        cli.restore_state();
+        cli
     }
 }
 
 impl<'a, 'b> Cli<'a> {
-    pub fn run(&mut self) {
+    /// Runs a session on the terminal, with line editing when stdin is one.
+    /// With the `rustyline` feature, rustyline does the editing instead of
+    /// the built-in editor.
+    #[cfg(feature = "interactive")]
+    pub fn run(&mut self) -> Result<(), CliError<'a>> {
+        use std::io::IsTerminal;
+
         let stdin = io::stdin();
-        let mut handle = stdin.lock();
+
+        // rustyline has nothing to edit on piped input, which keeps to the plain loop
+        #[cfg(feature = "rustyline")]
+        if stdin.is_terminal() {
+            self.interactive = true;
+            self.color = self.config.color && io::stdout().is_terminal();
+            let result = self.run_readline();
+            self.interactive = false;
+            self.color = false;
+
+            return result;
+        }
+
+        let raw_mode = editor::RawMode::enable(self.config.catch_interrupt);
+
+        // Without canonical mode the terminal no longer echoes for us
+        self.echo = raw_mode.is_some();
+        self.interactive = stdin.is_terminal();
+        self.color = self.config.color && io::stdout().is_terminal();
+        let result = self.run_with_streams(stdin.lock(), io::stdout(), io::stderr());
+        self.echo = false;
+        self.interactive = false;
+        self.color = false;
+
+        result
+    }
+
+    /// Like `run_with_streams`, but everything goes to `writer`.
+    pub fn run_with<R: BufRead, W: Write>(&mut self, reader: R, writer: W) -> Result<(), CliError<'a>> {
+        let writer = RefCell::new(writer);
+        self.run_with_streams(reader, Shared(&writer), Shared(&writer))
+    }
+
+    /// Reads commands from `reader` until EOF or an exit command. Results that
+    /// other programs may want, such as accepted commands and listings, go to
+    /// `out`. The prompt, usage and error messages go to `err`.
+    pub fn run_with_streams<R, O, E>(&mut self, mut reader: R, mut out: O, mut err: E) -> Result<(), CliError<'a>>
+    where
+        R: BufRead,
+        O: Write,
+        E: Write,
+    {
         let mut input = String::new();
 
+        if let Some(notice) = self.notice.take() {
+            writeln!(err, "{}", notice)?;
+        }
+
         loop {
-            print!("{}{}", self.current_prompt, self.config.prompt);
-            io::stdout().flush().expect("Failed to flush");
+            out.flush()?;
+            self.print_prompt(&mut err)?;
+            err.flush()?;
 
             input.clear();
-            match handle.read_line(&mut input) {
-                Ok(n) => {
-                    if Cli::should_exit(&input, n) {
-                        break;
-                    } else if Cli::should_new_prompt(&input) {
-                        continue;
-                    } else if Cli::should_change_root(&input) {
-                        println!("Change root!");
-                        let (new_root, new_prompt) = self.change_root(&input);
-                        self.current_root = new_root;
-                        self.current_prompt = new_prompt;
-                    } else {
-                        self.handle_input(&input);
-                    }
-                }
+            let n = match self.read_line(&mut reader, &mut err, &mut input) {
+                Ok(n) => n,
                 Err(e) => {
-                    println!("Got error: {}", e);
-                    break;
+                    // Keep what was entered so far even if the session dies
+                    self.save_history()?;
+                    return Err(e.into());
+                }
+            };
+
+            // End of input, e.g. Ctrl-D, leaves the cursor after the prompt
+            if n == 0 {
+                if self.interactive {
+                    writeln!(err, "logout")?;
+                } else {
+                    writeln!(err)?;
+                }
+                break;
+            }
+
+            let line = Cli::normalize(&input);
+            self.add_history(line);
+
+            if self.should_exit(line) {
+                // A terminal has already shown the line being entered
+                if !self.interactive {
+                    writeln!(err)?;
                 }
+                break;
+            } else if Cli::should_new_prompt(line) {
+                continue;
             }
+
+            self.dispatch(Cli::with_cd_whitespace(&input, line), &mut reader, &mut out, &mut err)?;
         }
-        println!("");
-        io::stdout().flush().expect("Failed to flush stdout");
-    }
 
-    fn should_exit(input: &'a str, nbytes: usize) -> bool {
-        nbytes == 0 || input == "exit\n" || input == "quit\n"
+        self.save_history()?;
+        self.save_state()?;
+        out.flush()?;
+        err.flush()?;
+
+        Ok(())
     }
 
-    fn should_new_prompt(input: &'a str) -> bool {
-        input == "\n"
+    /// Run the commands in the file at `path` as if they were typed, then
+    /// return. Blank lines and lines starting with `#` are skipped. Fails with
+    /// `CliError::Rejected` for the first line that wasn't accepted.
+    pub fn run_script(&mut self, path: &Path) -> Result<(), CliError<'a>> {
+        let script = fs::read_to_string(path)?;
+        let mut stdout = io::stdout();
+
+        let result = self.run_lines(&script, &mut stdout, &mut io::stderr());
+        stdout.flush()?;
+
+        result
     }
 
-    fn should_change_root(input: &'a str) -> bool {
-        if input.len() >= 2 {
-            &input[..2] == "cd"
-        } else {
-            false
+    fn run_lines<O: Write, E: Write>(&mut self, script: &str, out: &mut O, err: &mut E) -> Result<(), CliError<'a>> {
+        let mut first_failure = None;
+
+        for (i, line) in script.lines().enumerate() {
+            let input = Cli::normalize(line);
+            if input.is_empty() || input.starts_with('#') {
+                continue;
+            }
+            if self.should_exit(input) {
+                break;
+            }
+
+            // Echo each command so the output reads like an interactive session
+            out.flush()?;
+            self.print_prompt(err)?;
+            writeln!(err, "{}", input)?;
+
+            // Nobody is there to confirm commands that ask for it, so they don't run
+            if !self.dispatch(Cli::with_cd_whitespace(line, input), &mut io::empty(), out, err)? && first_failure.is_none() {
+                first_failure = Some(CliError::Rejected { line: i + 1, input: input.to_string() });
+                if self.config.stop_on_failure {
+                    break;
+                }
+            }
         }
-    }
 
-    fn change_root(&self, input: &'b str) -> (NodeId, String) {
-        let mut new_root = self.config.valid_cmds.root;
-        let mut construct_input = None;
-        
-        let mut input_stripped: String 
-            = input.chars().filter(|c| !c.is_whitespace()).collect();
-
-        if input_stripped == "cd" {
-        } else if input_stripped == "cd -" {
-            // Back to previous root if it exists
-            if let Some(proot) = self.prev_root {
-                new_root = proot;
-                construct_input = Some(&new_root);
-            }
-        } else if input == "cd ..\n" {
-                if let Some(parent) = self.current_root.ancestors(&self.config.valid_cmds.arena).next() {
-                    new_root = parent;
-                    construct_input = Some(&new_root);
-                }
-        } else if input.starts_with("cd /") {
-            // Absolute path
-            println!("{}", input);
-        } else if input.starts_with("cd ") {
-            // Relative path
-            if input_stripped.ends_with('/') {
-                input_stripped.pop();
-            }
-            let clicmds = Cli::construct_clicmds(&input_stripped[2..], '/');
-            let (_, root) = self.build_subtree(&clicmds);
-            new_root = root;
-
-            construct_input = Some(&new_root);
+        match first_failure {
+            Some(e) => Err(e),
+            None => Ok(()),
         }
-        
-        (new_root, self.construct_prompt(construct_input))
     }
 
-    fn construct_prompt(&self, root: Option<&NodeId>) -> String {
-        let mut prompt = String::new();
-        
-        if let Some(root) = root {
-            let arena = &self.config.valid_cmds.arena;
-    
-            let mut prompt_vec: Vec<String> = Vec::new();
-            for node in root.ancestors(&self.config.valid_cmds.arena) {
-                prompt_vec.push(
-                    Node::from_id(&node, &arena).name.to_string()
-                );
+    /// Handle one line of input other than an exit command. Returns whether it
+    /// succeeded: builtins succeed unless they report an error, and anything
+    /// else has to be accepted.
+    fn dispatch<R: BufRead, O: Write, E: Write>(&mut self, input: &str, reader: &mut R, out: &mut O, err: &mut E) -> io::Result<bool> {
+        if Cli::should_help(input) {
+            return self.help(input, out, err);
+        } else if Cli::should_list(input) {
+            return self.list(input, out, err);
+        } else if Cli::should_draw_tree(input) {
+            self.draw_tree(out)?;
+        } else if Cli::should_source(input) {
+            return self.source(input, out, err);
+        } else if Cli::should_print_path(input) {
+            writeln!(out, "{}", self.current_path())?;
+        } else if Cli::should_reload(input) {
+            return self.reload(err);
+        } else if Cli::should_set_dry_run(input) {
+            return self.set_dry_run(input, out, err);
+        } else if Cli::should_go_back(input) {
+            match self.visited.pop() {
+                Some(root) => {
+                    let prompt = self.construct_prompt(Some(&root));
+                    self.prev_root = Some(self.current_root);
+                    self.current_root = root;
+                    self.set_prompt(prompt);
+                }
+                None => {
+                    writeln!(err, "back: nowhere to go back to")?;
+                    return Ok(false);
+                }
             }
-    
-            for s in prompt_vec.into_iter().rev() {
-                if s != "root" {
-                    prompt.push_str(&s);
-                    prompt.push('/');
+        } else if Cli::should_change_root(input) {
+            match self.change_root(input) {
+                // Staying put, as with cd ., isn't somewhere to go back to
+                Ok((new_root, _)) if new_root == self.current_root => {}
+                Ok((new_root, new_prompt)) => {
+                    self.visited.push(self.current_root);
+                    if self.visited.len() > MAX_VISITED {
+                        self.visited.remove(0);
+                    }
+
+                    self.prev_root = Some(self.current_root);
+                    self.current_root = new_root;
+                    self.set_prompt(new_prompt);
+                }
+                Err(e) => {
+                    writeln!(err, "{}", e)?;
+                    return Ok(false);
                 }
             }
+        } else if Cli::should_show_options(input) {
+            return self.show_options(input, out, err);
+        } else {
+            return self.report_input(input, reader, out, err);
         }
-        
-        prompt
+
+        Ok(true)
     }
 
-    fn handle_input(&self, input: &'a str) {
-        let clicmds = Cli::construct_clicmds(&input, ' ');
-        let (sequence_tree, leaf) = self.build_subtree(&clicmds);
-        println!("{:?}", sequence_tree);
-        
-        let sequence_tree_count = translator::subtree_count(
-            &sequence_tree.root, 
-            &sequence_tree.arena
-        );
-        let nodes_below_leaf = translator::subtree_count(
-            &leaf, 
-            &self.config.valid_cmds.arena
-        );
-
-        println!("seq count: {}", sequence_tree_count);
-        println!("leaf below count: {}", nodes_below_leaf);
-
-        if sequence_tree_count == clicmds.len() && nodes_below_leaf == 0 {
-            println!("ACCEPTED");
-        } else {
-            println!("USAGE");
-            self.print_usage(&leaf, &sequence_tree);
+    fn set_prompt(&mut self, prompt: String) {
+        if prompt == self.current_prompt {
+            return;
+        }
+
+        if let Some(observer) = self.config.observer.as_mut() {
+            observer.on_prompt_change(&prompt);
         }
+        self.current_prompt = prompt;
     }
 
-    fn construct_clicmds(input: &'a str, delim: char) -> Vec<CliCmd> {
-        let mut clicmds = vec![];
-        for (i, split) in input.split(delim).enumerate() {
-            clicmds.push(
-                CliCmd {
-                    cmd: if split.ends_with('\n') {
-                        &split[0..split.len() - 1]
-                    } else {
-                        split
-                    },
-                    depth: Depth::Some(i + 1)
-                }
-            );
+    fn print_prompt<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (start, end) = self.style(CYAN);
+        let path = format!("{}{}{}", start, self.current_prompt, end);
+
+        match &self.config.prompt_format {
+            Some(parts) => write!(writer, "{}", parts.join(&path)),
+            None => write!(writer, "{}{}", path, self.config.prompt),
         }
+    }
 
-        clicmds
+    /// Escape codes to put around text, or nothing when color is off.
+    fn style(&self, color: &'static str) -> (&'static str, &'static str) {
+        if cfg!(feature = "color") && self.color {
+            (color, RESET)
+        } else {
+            ("", "")
+        }
     }
 
-    fn build_subtree(&self, clicmds: &Vec<CliCmd>) -> (Tree<'a>, NodeId) {
-        /*
-        At this point we may have a validation tree looking like this:
+    fn read_line<R: BufRead, W: Write>(&self, reader: &mut R, writer: &mut W, line: &mut String) -> io::Result<usize> {
+        // Position while browsing history, and the line typed before browsing started
+        let mut history_index = self.history.len();
+        let mut draft = String::new();
+        // Byte offset of the cursor in `line`
+        let mut cursor = 0;
 
-                        root
-                  -------|-------
-                 sat            gs
-             -----|-----    -----|-----
-            cmd        ft radio     config
-         ----|-------
-        obc  adcs  pay
-         |
-        ping
+        loop {
+            let key = match editor::read_key(reader)? {
+                Key::Interrupt if !self.config.catch_interrupt => Key::Char('\x03'),
+                key => key,
+            };
+            match key {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += c.len_utf8();
+                    if cursor == line.len() {
+                        if self.echo {
+                            write!(writer, "{}", c)?;
+                        }
+                    } else {
+                        self.redraw(writer, line, cursor)?;
+                    }
+                }
+                Key::Backspace => {
+                    if let Some(c) = line[..cursor].chars().next_back() {
+                        cursor -= c.len_utf8();
+                        line.remove(cursor);
+                        if cursor == line.len() {
+                            if self.echo {
+                                write!(writer, "\x08 \x08")?;
+                            }
+                        } else {
+                            self.redraw(writer, line, cursor)?;
+                        }
+                    }
+                }
+                Key::Left => {
+                    if let Some(c) = line[..cursor].chars().next_back() {
+                        cursor -= c.len_utf8();
+                        self.redraw(writer, line, cursor)?;
+                    }
+                }
+                Key::Right => {
+                    if let Some(c) = line[cursor..].chars().next() {
+                        cursor += c.len_utf8();
+                        self.redraw(writer, line, cursor)?;
+                    }
+                }
+                Key::Home => {
+                    cursor = 0;
+                    self.redraw(writer, line, cursor)?;
+                }
+                Key::End => {
+                    cursor = line.len();
+                    self.redraw(writer, line, cursor)?;
+                }
+                Key::DeleteWord => {
+                    let start = editor::word_start(line, cursor);
+                    line.replace_range(start..cursor, "");
+                    cursor = start;
+                    self.redraw(writer, line, cursor)?;
+                }
+                Key::DeleteLine => {
+                    line.replace_range(..cursor, "");
+                    cursor = 0;
+                    self.redraw(writer, line, cursor)?;
+                }
+                // Completion works on the last word, so only at the end of the line
+                Key::Tab if cursor < line.len() => {}
+                Key::Tab => {
+                    let (start, candidates) = self.complete(line);
+                    let partial_len = line.len() - start;
+                    let prefix = editor::common_prefix(&candidates);
 
-        Given an input like: "sat cmd obc ping", we want to reach the ping leaf
-        from the root node. If the algorithm does not find its necessary node,
-        it will end prematurely, which in turn outputs a shorter tree than expected.
-        */
-        
-        let validation_tree = &self.config.valid_cmds;
-        let mut root = self.current_root;
-        let mut seq_tree = Tree::new();
+                    if prefix.len() > partial_len {
+                        line.push_str(&prefix[partial_len..]);
+                        if self.echo {
+                            write!(writer, "{}", &prefix[partial_len..])?;
+                        }
+                    }
+                    cursor = line.len();
 
-        let up_clicmd = CliCmd { cmd: "..", depth: Depth::Any };
+                    if candidates.len() > 1 {
+                        writeln!(writer)?;
+                        writeln!(writer, "{}", candidates.join("  "))?;
+                        self.print_prompt(writer)?;
+                        write!(writer, "{}", line)?;
+                    }
+                }
+                Key::Up | Key::Down => {
+                    if history_index == self.history.len() {
+                        draft = line.clone();
+                    }
 
-        'upper: for cmd in clicmds {
-            if *cmd == up_clicmd {
-                if let Some(node) = root.ancestors(&validation_tree.arena).next() {
-                    let append = Node::from_id(&node, &validation_tree.arena);
-                    seq_tree.root.append(
-                        Node::from_node_to_id(append, &mut seq_tree.arena),
-                        &mut seq_tree.arena
-                    );
+                    history_index = match key {
+                        Key::Up => history_index.saturating_sub(1),
+                        _ => (history_index + 1).min(self.history.len()),
+                    };
 
-                    root = node;
+                    line.clear();
+                    line.push_str(self.history.get(history_index).unwrap_or(&draft));
+                    cursor = line.len();
+                    self.redraw(writer, line, cursor)?;
                 }
-            }
-            for child in root.children(&validation_tree.arena) {
-                let node = Node::from_id(&child, &validation_tree.arena);
-                println!("data: {:?}", node);
-                println!("cmd: {:?}", cmd);
-                if *cmd == node {
+                Key::Other => {}
+                Key::Interrupt => {
+                    if self.echo {
+                        writeln!(writer, "^C")?;
+                    }
+                    if line.is_empty() {
+                        return Ok(0);
+                    }
 
-                    // Build up the sequence tree so we can return it later
-                    seq_tree.root.append(
-                        Node::from_node_to_id(node, &mut seq_tree.arena), 
-                        &mut seq_tree.arena
-                    );
-                    
-                    // Update root so next iterations begins from the subtree
-                    root = child;
-                    continue 'upper;
+                    line.clear();
+                    cursor = 0;
+                    history_index = self.history.len();
+                    self.print_prompt(writer)?;
+                }
+                Key::Enter => {
+                    line.push('\n');
+                    if self.echo {
+                        writeln!(writer)?;
+                    }
+                    return Ok(line.len());
+                }
+                Key::Eof => {
+                    return Ok(line.len());
                 }
             }
+            writer.flush()?;
+        }
+    }
 
-            // cmd did not match any node in the tree; end prematurely
-            break;
+    /// Rewrites the prompt and `line`, leaving the terminal cursor at `cursor`.
+    fn redraw<W: Write>(&self, writer: &mut W, line: &str, cursor: usize) -> io::Result<()> {
+        if !self.echo {
+            return Ok(());
         }
 
-        // On success, root has become a leaf
-        (seq_tree, root)
+        write!(writer, "\r\x1b[K")?;
+        self.print_prompt(writer)?;
+        write!(writer, "{}", line)?;
+
+        let after = line[cursor..].chars().count();
+        if after > 0 {
+            write!(writer, "\x1b[{}D", after)?;
+        }
+        Ok(())
     }
 
-    fn print_usage(&self, last_valid_node: &NodeId, sequence_tree: &Tree) {
-        print!("Usage: ");
-        
-        for node in sequence_tree.root.descendants(&sequence_tree.arena).skip(1) {
-            let node = Node::from_id(&node, &sequence_tree.arena);
-            print!("{} ", node.name);
+    fn add_history(&mut self, input: &str) {
+        if input.is_empty() || self.config.exit_cmds.contains(&input) {
+            return;
         }
 
-        print!("<cmd>\nWhere 'cmd' can be either of\n");
+        // Running the same command again doesn't need recalling twice
+        if self.history.last().is_some_and(|last| last == input) {
+            return;
+        }
 
-        let validation_tree = &self.config.valid_cmds;
-        for node in last_valid_node.children(&validation_tree.arena) {
-            let node = Node::from_id(&node, &validation_tree.arena);
-            print!("\t* {}", node.name);
-            
-            if let Some(exp) = node.explanation {
-                print!(": {}", exp);
-            }
+        self.history.push(input.to_string());
+        self.new_history += 1;
+        self.truncate_history();
+    }
 
-            println!("");
-        }    
+    fn truncate_history(&mut self) {
+        let size = self.config.history_size;
+        if self.history.len() > size {
+            self.history.drain(..self.history.len() - size);
+        }
+        self.new_history = self.new_history.min(self.history.len());
     }
-}
 
-/* fn _check_timeout(_prev_timeout: Duration, timeout: Option<Duration>) -> bool {
-    match timeout {
-        Some(_t) => false,
-        None => false,
+    fn save_history(&mut self) -> io::Result<()> {
+        if let Some(path) = self.config.history_file {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            for cmd in &self.history[self.history.len() - self.new_history..] {
+                writeln!(file, "{}", cmd)?;
+            }
+        }
+        self.new_history = 0;
+        Ok(())
     }
-} */
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use yaml_rust::YamlLoader;
-    use translator::yaml;
+    // Moves to the path saved by `save_state`, if it's still in the tree
+    fn restore_state(&mut self) {
+        // A missing state file just means nothing has been saved yet
+        let saved = match self.config.state_file.map(fs::read_to_string) {
+            Some(Ok(saved)) => saved,
+            _ => return,
+        };
 
-    const YAMLDOC: &str =
-    "
-    sat:
-    - obc
-      - ping
-      - set
-    - adcs
-      - ping
-      - set
-    - pay
-      - ping
-      - take_pic
-    
-    gs:
-    - radio
-      - ping
-      - set_freq
-    - sys
-      - config
-    ";
+        let path = saved.trim();
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        match self.config.valid_cmds.find_by_path(&segments) {
+            Some(root) => {
+                self.current_root = root;
+                self.set_prompt(self.construct_prompt(Some(&root)));
+            }
+            None => self.notice = Some(format!("warning: {} no longer exists, starting at /", path)),
+        }
+    }
 
-    fn get_cli<'a>(yaml: &'a yaml_rust::Yaml) -> Cli<'a> {
-        let cmd_tree = yaml::to_tree(&yaml);
+    fn save_state(&self) -> io::Result<()> {
+        match self.config.state_file {
+            // Always with `/`, so that the saved path outlives a change of separator
+            Some(path) => fs::write(path, format!("/{}\n", self.path_of(&self.current_root).join("/"))),
+            None => Ok(()),
+        }
+    }
+
+    fn complete(&self, line: &str) -> (usize, Vec<&str>) {
+        let config = &self.config;
+        completions(&config.valid_cmds, self.current_root, config.delimiter, config.path_separator, config.case_insensitive, line)
+    }
+
+    /// Strips the line ending, whether `\n` or `\r\n`, and surrounding
+    /// whitespace, so everything downstream can compare input as typed.
+    fn normalize(input: &str) -> &str {
+        input.trim()
+    }
+
+    /// `cd ` stays put where a bare `cd` goes to the root, so unlike other
+    /// input it keeps the trailing whitespace that `normalize` drops.
+    fn with_cd_whitespace<'i>(raw: &'i str, line: &'i str) -> &'i str {
+        if line == "cd" {
+            raw.trim_start().trim_end_matches(['\n', '\r'])
+        } else {
+            line
+        }
+    }
+
+    fn should_exit(&self, input: &str) -> bool {
+        self.config.exit_cmds.contains(&input)
+    }
+
+    fn should_new_prompt(input: &'a str) -> bool {
+        input.is_empty()
+    }
+
+    fn should_change_root(input: &'a str) -> bool {
+        Cli::is_builtin(input, "cd")
+    }
+
+    /// `back` returns to where the last `cd` came from, and repeating it keeps
+    /// going back through earlier ones. Unlike `cd -`, which is itself a `cd`
+    /// and so swaps between two places, `back` is not recorded. Either way
+    /// `cd -` afterwards returns to where `back` left.
+    fn should_go_back(input: &'a str) -> bool {
+        input == "back"
+    }
+
+    fn should_reload(input: &'a str) -> bool {
+        input == "reload"
+    }
+
+    fn should_help(input: &'a str) -> bool {
+        Cli::is_builtin(input, "help")
+    }
+
+    fn should_list(input: &'a str) -> bool {
+        Cli::is_builtin(input, "ls")
+    }
+
+    fn should_draw_tree(input: &'a str) -> bool {
+        input == "tree"
+    }
+
+    fn should_source(input: &'a str) -> bool {
+        Cli::is_builtin(input, "source")
+    }
+
+    fn should_print_path(input: &'a str) -> bool {
+        input == "pwd"
+    }
+
+    fn should_set_dry_run(input: &'a str) -> bool {
+        Cli::is_builtin(input, "dryrun")
+    }
+
+    /// A trailing `?`, as in `sat obc?`, asks what can follow without
+    /// running anything.
+    fn should_show_options(input: &'a str) -> bool {
+        input.trim_end().ends_with('?')
+    }
+
+    fn is_builtin(input: &str, name: &str) -> bool {
+        match input.strip_prefix(name) {
+            Some(rest) => rest.is_empty() || rest.starts_with(char::is_whitespace),
+            None => false,
+        }
+    }
+
+    fn help<O: Write, E: Write>(&self, input: &str, out: &mut O, err: &mut E) -> io::Result<bool> {
+        let cmd = input["help".len()..].trim();
+
+        if cmd.is_empty() {
+            self.print_children(&self.current_root, out)?;
+            return Ok(true);
+        }
+
+        match self.resolve_path(self.current_root, cmd.split(self.config.delimiter)) {
+            Ok(node) => self.print_node(&node, out)?,
+            Err(_) => {
+                writeln!(err, "No such command: {}", cmd)?;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn show_options<O: Write, E: Write>(&self, input: &str, out: &mut O, err: &mut E) -> io::Result<bool> {
+        let cmd = input.trim_end().trim_end_matches('?');
+
+        if cmd.trim().is_empty() {
+            self.print_children(&self.current_root, out)?;
+            return Ok(true);
+        }
+
+        match self.handle_input(cmd) {
+            // A complete command has nothing after it, but may say what it does
+            Ok(matched) => {
+                let names: Vec<&str> = matched.iter().map(|node| &*node.name).collect();
+                write!(out, "Usage: {}", names.join(&self.config.delimiter.to_string()))?;
+                if let Some(exp) = matched.last().and_then(|node| node.explanation.as_deref()) {
+                    let (start, end) = self.style(GRAY);
+                    write!(out, ": {}{}{}", start, exp, end)?;
+                }
+                writeln!(out)?;
+            }
+            Err(InputError::Usage(usage)) if usage.is_incomplete() => self.print_options(&usage, out)?,
+            Err(InputError::Usage(usage)) => {
+                self.print_usage(&usage, err)?;
+                return Ok(false);
+            }
+            Err(e) => {
+                writeln!(err, "{}", e)?;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// `dryrun on` and `dryrun off` switch dry-run mode, `dryrun` alone shows it.
+    fn set_dry_run<O: Write, E: Write>(&mut self, input: &str, out: &mut O, err: &mut E) -> io::Result<bool> {
+        match input["dryrun".len()..].trim() {
+            "on" => self.dry_run = true,
+            "off" => self.dry_run = false,
+            "" => writeln!(out, "dryrun {}", if self.dry_run { "on" } else { "off" })?,
+            _ => {
+                writeln!(err, "Usage: dryrun [on|off]")?;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn list<O: Write, E: Write>(&self, input: &str, out: &mut O, err: &mut E) -> io::Result<bool> {
+        let arena = &self.config.valid_cmds.arena;
+        let path = input["ls".len()..].trim();
+
+        let node = match self.resolve(path) {
+            Ok(node) => node,
+            Err(e) => {
+                writeln!(err, "{}", e)?;
+                return Ok(false);
+            }
+        };
+
+        let entries: Vec<(String, Option<&str>)> = self
+            .listed_children(&node)
+            .map(|child| {
+                let node = arena[child].get();
+
+                // Mark nodes that can be cd'd into, like directories
+                let name = if !self.config.valid_cmds.is_leaf(child) {
+                    format!("{}{}", node.name, self.config.path_separator)
+                } else {
+                    node.name.to_string()
+                };
+                (name, node.explanation.as_deref())
+            })
+            .collect();
+
+        // Names alone fit on one line; explanations get a line per command
+        if entries.iter().all(|(_, exp)| exp.is_none()) {
+            let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+            writeln!(out, "{}", names.join("  "))?;
+            return Ok(true);
+        }
+
+        let width = entries.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+        let (start, end) = self.style(GRAY);
+        for (name, exp) in entries {
+            match exp {
+                Some(exp) => writeln!(out, "{:<width$}  {}{}{}", name, start, exp, end, width = width)?,
+                None => writeln!(out, "{}", name)?,
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn draw_tree<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // Commands with subcommands stand out the same way they do in prompts
+        let (start, end) = self.style(CYAN);
+        let drawing = self.config.valid_cmds.draw_with(self.current_root, |node, branch| {
+            if branch {
+                format!("{}{}{}", start, node.name, end)
+            } else {
+                node.name.to_string()
+            }
+        });
+
+        writeln!(writer, "{}", self.current_path())?;
+        write!(writer, "{}", drawing)
+    }
+
+    /// Run the commands in a file as if they were typed here. Any `cd` in the
+    /// file only lasts until it ends; afterwards we are back where we started.
+    fn source<O: Write, E: Write>(&mut self, input: &str, out: &mut O, err: &mut E) -> io::Result<bool> {
+        let path = input["source".len()..].trim();
+        if path.is_empty() {
+            writeln!(err, "Usage: source <file>")?;
+            return Ok(false);
+        }
+
+        let read = fs::canonicalize(path).and_then(|canonical| {
+            let script = fs::read_to_string(&canonical)?;
+            Ok((canonical, script))
+        });
+        let (canonical, script) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                writeln!(err, "source: {}: {}", path, e)?;
+                return Ok(false);
+            }
+        };
+
+        if self.sourcing.contains(&canonical) {
+            writeln!(err, "source: {}: already being sourced", path)?;
+            return Ok(false);
+        }
+
+        let (root, prev_root, prompt) = (self.current_root, self.prev_root, self.current_prompt.clone());
+        let visited = self.visited.clone();
+        let tree = self.config.valid_cmds.node_ref(root);
+        self.sourcing.push(canonical);
+
+        let result = self.run_lines(&script, out, err);
+
+        self.sourcing.pop();
+        // After a reload in the file the old places are gone, so stay put
+        if self.config.valid_cmds.owns(tree) {
+            self.current_root = root;
+            self.prev_root = prev_root;
+            self.set_prompt(prompt);
+            self.visited = visited;
+        }
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(CliError::Io(e)) => Err(e),
+            Err(e) => {
+                writeln!(err, "source: {}: {}", path, e)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Replaces the command tree with a freshly loaded one and starts over
+    /// from its root. The old tree is kept if loading fails.
+    fn reload<E: Write>(&mut self, err: &mut E) -> io::Result<bool> {
+        let tree = match self.config.reloader.as_mut() {
+            Some(loader) => loader(),
+            None => {
+                writeln!(err, "reload: nothing to reload from")?;
+                return Ok(false);
+            }
+        };
+
+        match tree {
+            Ok(tree) => {
+                // Ids into the old tree mean nothing in the new one
+                self.current_root = tree.root;
+                self.prev_root = None;
+                self.visited.clear();
+                self.set_prompt(String::new());
+                self.config.valid_cmds = tree;
+                Ok(true)
+            }
+            Err(e) => {
+                writeln!(err, "reload: {}", e)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Where `cd` with `input` goes and the prompt there. A bare `cd` goes to
+    /// the tree root, while `cd .` and `cd` followed by nothing but
+    /// whitespace stay put.
+    fn change_root(&self, input: &'b str) -> Result<(NodeId, String), CliError<'b>> {
+        let root = self.config.valid_cmds.root;
+        let rest = input[2..].trim_end_matches(['\n', '\r']);
+        let path = rest.trim();
+
+        if !rest.is_empty() && (path.is_empty() || path == ".") {
+            return Ok((self.current_root, self.current_prompt.clone()));
+        }
+
+        let new_root = if path.is_empty() {
+            root
+        } else if path == "-" {
+            // Back to previous root if it exists, otherwise stay put
+            self.prev_root.unwrap_or(self.current_root)
+        } else {
+            let node = self.resolve(path)?;
+            // Nothing can be entered below a command, so there's no going there
+            if self.config.valid_cmds.is_leaf(node) {
+                return Err(CliError::NotAGroup(path));
+            }
+            node
+        };
+
+        Ok((new_root, self.construct_prompt(Some(&new_root))))
+    }
+
+    /// The node at `path`, from the tree root if it starts with the path
+    /// separator and from the current root otherwise.
+    fn resolve<'p>(&self, path: &'p str) -> Result<NodeId, CliError<'p>> {
+        let separator = self.config.path_separator;
+        let node = match path.strip_prefix(separator) {
+            Some(absolute) => self.resolve_path(self.config.valid_cmds.root, absolute.split(separator)),
+            None => self.resolve_path(self.current_root, path.split(separator)),
+        };
+
+        node.map_err(|candidates| {
+            if candidates.is_empty() {
+                CliError::InvalidPath(path)
+            } else {
+                CliError::AmbiguousPath { path, candidates: candidates.into_iter().map(String::from).collect() }
+            }
+        })
+    }
+
+    /// Follows `segments` down from `from`. Each segment may also be the
+    /// start of a single listed command's name, as in `cd sat/ob`. When one
+    /// isn't, the error holds the names it starts, if any.
+    fn resolve_path<'s>(&self, from: NodeId, segments: impl Iterator<Item = &'s str>) -> Result<NodeId, Vec<&str>> {
+        let arena = &self.config.valid_cmds.arena;
+        let mut node = from;
+
+        for segment in segments.map(str::trim).filter(|s| !s.is_empty()) {
+            node = match segment {
+                "." => node,
+                // Climbing past the tree root leaves us at the root
+                ".." => arena[node].parent().unwrap_or(node),
+                name => self.child_by_prefix(node, name)?,
+            };
+        }
+
+        Ok(node)
+    }
+
+    fn child_by_prefix(&self, node: NodeId, name: &str) -> Result<NodeId, Vec<&str>> {
+        let arena = &self.config.valid_cmds.arena;
+        let eq = |a: &str, b: &str| {
+            if self.config.case_insensitive { a.eq_ignore_ascii_case(b) } else { a == b }
+        };
+
+        let exact = node
+            .children(arena)
+            .find(|child| arena[*child].get().names().any(|n| eq(n, name)));
+        if let Some(child) = exact {
+            return Ok(child);
+        }
+
+        let starts: Vec<NodeId> = self
+            .listed_children(&node)
+            .filter(|child| {
+                arena[*child].get().names().any(|n| n.get(..name.len()).is_some_and(|start| eq(start, name)))
+            })
+            .collect();
+
+        match starts[..] {
+            [child] => Ok(child),
+            _ => Err(starts.iter().map(|child| &*arena[*child].get().name).collect()),
+        }
+    }
+
+    /// Names from the tree root down to `node`, excluding the root itself.
+    fn path_of(&self, node: &NodeId) -> Vec<&str> {
+        self.config.valid_cmds.path_of(*node)
+    }
+
+    /// Where `cd` has taken us, as `pwd` prints it: `/sat/obc`, or `/` at
+    /// the root.
+    fn current_path(&self) -> String {
+        self.absolute_path(&self.current_root)
+    }
+
+    fn absolute_path(&self, node: &NodeId) -> String {
+        format!("{}{}", self.config.path_separator, self.construct_prompt(Some(node)))
+    }
+
+    fn construct_prompt(&self, root: Option<&NodeId>) -> String {
+        match root {
+            Some(root) => self.path_of(root).join(&self.config.path_separator.to_string()),
+            None => String::new(),
+        }
+    }
+
+    /// Validates `input` against the command tree from the current root,
+    /// without printing anything. On success these are the commands that were
+    /// matched, each followed by its argument value if it takes one.
+    ///
+    /// Input starting with `/` is matched from the tree root instead, and its
+    /// first word may be a whole path, as in `/sat/obc/ping`.
+    pub fn handle_input<'c>(&self, input: &'c str) -> Result<Vec<Node<'c>>, InputError<'c>>
+    where
+        'a: 'c,
+    {
+        let input = Cli::normalize(input);
+        let (from, clicmds) = match input.strip_prefix(self.config.path_separator) {
+            Some(absolute) => {
+                let clicmds = Cli::construct_clicmds(absolute, self.config.delimiter)?;
+                (self.config.valid_cmds.root, Cli::split_path(clicmds, self.config.path_separator))
+            }
+            None => (self.current_root, Cli::construct_clicmds(input, self.config.delimiter)?),
+        };
+        let (sequence_tree, leaf) = self.build_subtree(from, &clicmds);
+        let leaf = self.config.valid_cmds.resolve(leaf);
         
-        let config = CliConfig::new(
-            "$: ", 
-            cmd_tree,
-        ).expect("Invalid configuration");
-    
-        Cli::open(config)
+        let matched: Vec<Node<'c>> = sequence_tree.iter().skip(1).collect();
+        let is_leaf = self.config.valid_cmds.is_leaf(leaf);
+
+        if self.config.verbose {
+            eprintln!("{:?}", sequence_tree);
+            eprintln!("seq count: {}", matched.len());
+            eprintln!("leaf: {}", is_leaf);
+        }
+
+        // Every word matched, and the last one is a command rather than a group
+        if matched.len() == clicmds.len() && is_leaf {
+            return Ok(matched);
+        }
+
+        let arena = &self.config.valid_cmds.arena;
+        Err(InputError::Usage(UsageError {
+            unmatched: clicmds.into_iter().nth(matched.len()).map(|cmd| cmd.cmd),
+            matched,
+            last_valid_node: leaf,
+            expected: self.listed_children(&leaf).map(|child| Node::from_id(&child, arena)).collect(),
+        }))
+    }
+
+    /// Like `handle_input`, but sorts the outcome into what a caller checking
+    /// lines without a prompt, such as a test or a service, needs to know.
+    pub fn validate<'c>(&self, line: &'c str) -> ValidationResult<'c>
+    where
+        'a: 'c,
+    {
+        match self.handle_input(line) {
+            Ok(matched) => ValidationResult::Accepted(matched),
+            Err(InputError::Usage(usage)) if usage.is_incomplete() => ValidationResult::Incomplete {
+                last_valid: usage.last_valid_node,
+                options: usage.expected,
+            },
+            Err(_) => ValidationResult::Unknown,
+        }
+    }
+
+    /// Every command that can be run, for tools that need the whole grammar.
+    /// In `OutputMode::Human` that's a `/sat/obc/ping\texplanation` line per
+    /// command, in `OutputMode::Json` an array of records such as
+    /// `{"path":["sat","obc","ping"],"explanation":null}`. Hidden commands
+    /// and those below hidden ones are left out.
+    pub fn export_grammar(&self) -> String {
+        let tree = &self.config.valid_cmds;
+        let commands = tree.leaves().filter(|leaf| tree.ancestors(*leaf).all(|node| !node.hidden));
+
+        match self.config.output_mode {
+            OutputMode::Human => commands
+                .map(|leaf| {
+                    let explanation = tree.arena[leaf].get().explanation.as_deref().unwrap_or("");
+                    format!("{}\t{}\n", self.absolute_path(&leaf), explanation)
+                })
+                .collect(),
+            OutputMode::Json => {
+                let records: Vec<_> = commands
+                    .map(|leaf| serde_json::json!({
+                        "path": tree.path_of(leaf),
+                        "explanation": tree.arena[leaf].get().explanation,
+                    }))
+                    .collect();
+                serde_json::Value::Array(records).to_string()
+            }
+        }
+    }
+
+    /// Runs `handle_input` and prints the outcome the way the prompt does.
+    /// Commands marked `confirm` only run if the next line from `reader` is
+    /// a `y`.
+    fn report_input<R, O, E>(&mut self, input: &str, reader: &mut R, out: &mut O, err: &mut E) -> io::Result<bool>
+    where
+        R: BufRead,
+        O: Write,
+        E: Write,
+    {
+        let matched = match self.handle_input(input) {
+            Ok(matched) => matched,
+            Err(InputError::Usage(usage)) => {
+                writeln!(err, "USAGE")?;
+                self.print_usage(&usage, err)?;
+                if let Some(observer) = self.config.observer.as_mut() {
+                    observer.on_usage(usage.last_valid_node, &usage.expected);
+                }
+                return Ok(false);
+            }
+            Err(e) => {
+                writeln!(err, "{}", e)?;
+                return Ok(false);
+            }
+        };
+
+        if !self.dry_run && matched.iter().any(|node| node.confirm) {
+            write!(err, "Are you sure? [y/N] ")?;
+            err.flush()?;
+
+            let mut answer = String::new();
+            self.read_line(reader, err, &mut answer)?;
+            if !Cli::normalize(&answer).eq_ignore_ascii_case("y") {
+                writeln!(err, "Cancelled")?;
+                return Ok(false);
+            }
+        }
+
+        let (names, values) = Cli::split_values(&matched);
+        let command = self.command_handler(input, &names);
+
+        if self.config.output_mode == OutputMode::Json {
+            let path: Vec<&str> = matched.iter().map(|node| &*node.name).collect();
+            let status = if self.dry_run { "dry_run" } else { "accepted" };
+            let record = serde_json::json!({ "path": path, "status": status });
+
+            match self.config.record_sink.as_mut() {
+                Some(sink) => {
+                    writeln!(sink, "{}", record)?;
+                    sink.flush()?;
+                }
+                None => writeln!(out, "{}", record)?,
+            }
+        } else if self.dry_run {
+            let path: Vec<&str> = matched.iter().map(|node| &*node.name).collect();
+            writeln!(out, "Would execute {}", path.join(&self.config.delimiter.to_string()))?;
+        } else if self.config.print_accepted && command.is_none() {
+            write!(out, "ACCEPTED")?;
+
+            // Argument values directly follow their command in the sequence
+            let mut seq = matched.iter();
+            while let Some(node) = seq.next() {
+                if let Some(arg) = &node.arg {
+                    if let Some(value) = seq.next() {
+                        write!(out, " {}={}", arg.name, value.name)?;
+                    }
+                }
+            }
+            writeln!(out)?;
+        }
+
+        if self.dry_run {
+            return Ok(true);
+        }
+
+        if let Some(observer) = self.config.observer.as_mut() {
+            let path: Vec<&str> = matched.iter().map(|node| &*node.name).collect();
+            observer.on_accept(&path);
+        }
+
+        if let Some(i) = command {
+            (self.config.commands[i].1)(&values);
+        }
+
+        let path: Vec<&str> = matched.iter().map(|node| &*node.name).collect();
+        // The deepest command with a timeout of its own decides
+        let timeout = matched.iter().rev().find_map(|node| node.timeout).or(self.config.timeout);
+
+        if let Some(handler) = self.config.on_accept.as_mut() {
+            handler(&path, timeout);
+        }
+
+        if let Some(dispatcher) = self.config.dispatcher.as_mut() {
+            if !dispatcher.run(&path, timeout) {
+                let command = path.join(&self.config.delimiter.to_string());
+                match timeout {
+                    Some(timeout) => writeln!(err, "No response from '{}' within {:?}", command, timeout)?,
+                    None => writeln!(err, "No response from '{}'", command)?,
+                }
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Which of the `on_command` handlers is for the command `names` were
+    /// matched to. Unless `input` is absolute, they were matched from the
+    /// current root, so its path comes first.
+    fn command_handler(&self, input: &str, names: &[&str]) -> Option<usize> {
+        let separator = self.config.path_separator;
+        let mut path = if Cli::normalize(input).starts_with(separator) {
+            Vec::new()
+        } else {
+            self.path_of(&self.current_root)
+        };
+        path.extend_from_slice(names);
+
+        self.config.commands.iter().position(|(command, _)| {
+            command.trim_start_matches(separator).split(separator).eq(path.iter().copied())
+        })
+    }
+
+    /// Splits an accepted sequence into the names of its commands and the
+    /// values given for their arguments, which follow their command.
+    fn split_values<'c>(matched: &'c [Node]) -> (Vec<&'c str>, Vec<&'c str>) {
+        let mut names = Vec::new();
+        let mut values = Vec::new();
+
+        let mut seq = matched.iter();
+        while let Some(node) = seq.next() {
+            names.push(&*node.name);
+            if node.arg.is_some() {
+                if let Some(value) = seq.next() {
+                    values.push(&*value.name);
+                }
+            }
+        }
+
+        (names, values)
+    }
+
+    fn construct_clicmds(input: &str, delim: char) -> Result<Vec<CliCmd<'_>>, InputError<'_>> {
+        let mut clicmds = vec![];
+        for (i, word) in tokenize(input, delim)?.into_iter().enumerate() {
+            clicmds.push(
+                CliCmd {
+                    cmd: word,
+                    depth: Depth::Some(i + 1)
+                }
+            );
+        }
+
+        Ok(clicmds)
+    }
+
+    /// Splits the first of `clicmds` at each `separator`, unless it was quoted.
+    fn split_path(clicmds: Vec<CliCmd<'_>>, separator: char) -> Vec<CliCmd<'_>> {
+        let mut clicmds = clicmds.into_iter();
+        let first: Vec<Cow<str>> = match clicmds.next() {
+            Some(CliCmd { cmd: Cow::Borrowed(path), .. }) => {
+                path.split(separator).filter(|segment| !segment.is_empty()).map(Cow::Borrowed).collect()
+            }
+            Some(quoted) => vec![quoted.cmd],
+            None => vec![],
+        };
+
+        first
+            .into_iter()
+            .chain(clicmds.map(|clicmd| clicmd.cmd))
+            .enumerate()
+            .map(|(i, cmd)| CliCmd { cmd, depth: Depth::Some(i + 1) })
+            .collect()
+    }
+
+    /// Matches `clicmds` from `from` down. Returns the matched nodes as a tree
+    /// of their own and the last node that matched, which belongs to the
+    /// validation tree rather than the returned one.
+    fn build_subtree<'c>(&self, from: NodeId, clicmds: &[CliCmd<'c>]) -> (Tree<'c>, TreeRef)
+    where
+        'a: 'c,
+    {
+        /*
+        At this point we may have a validation tree looking like this:
+
+                        root
+                  -------|-------
+                 sat            gs
+             -----|-----    -----|-----
+            cmd        ft radio     config
+         ----|-------
+        obc  adcs  pay
+         |
+        ping
+
+        Given an input like: "sat cmd obc ping", we want to reach the ping leaf
+        from the root node. If the algorithm does not find its necessary node,
+        it will end prematurely, which in turn outputs a shorter tree than expected.
+
+        A child named '*' matches any word, but only if no sibling matches it by
+        name. The sequence tree records the word that was actually typed.
+
+        Children of the tree root with Depth::Any are global: when nothing at
+        the current level matches a word, not even a '*', it is looked up among
+        them, wherever the input has got to.
+        */
+        
+        let validation_tree = &self.config.valid_cmds;
+        let mut root = from;
+        let mut seq_tree = Tree::new();
+
+        let up_clicmd = CliCmd { cmd: "..".into(), depth: Depth::Any };
+        let mut cmds = clicmds.iter();
+
+        while let Some(cmd) = cmds.next() {
+            if *cmd == up_clicmd {
+                if let Some(node) = root.ancestors(&validation_tree.arena).next() {
+                    let append = Node::from_id(&node, &validation_tree.arena);
+                    seq_tree.root.append(
+                        Node::from_node_to_id(append, &mut seq_tree.arena),
+                        &mut seq_tree.arena
+                    );
+
+                    root = node;
+                }
+            }
+
+            // Depths in clicmds count from the tree root, but we may start further down
+            let root_node = match Node::try_from_id(&root, &validation_tree.arena) {
+                Some(node) => node,
+                None => break,
+            };
+            let cmd = CliCmd {
+                cmd: cmd.cmd.clone(),
+                depth: match root_node.depth {
+                    Depth::Some(d) => Depth::Some(d + 1),
+                    Depth::Any => Depth::Any,
+                },
+            };
+            let mut matched = None;
+            let mut wildcard = None;
+
+            for child in root.children(&validation_tree.arena) {
+                let node = Node::from_id(&child, &validation_tree.arena);
+                if self.config.verbose {
+                    eprintln!("data: {:?}", node);
+                    eprintln!("cmd: {:?}", cmd);
+                }
+                if cmd.matches(&node, self.config.case_insensitive) {
+                    matched = Some((child, node));
+                    break;
+                } else if wildcard.is_none() && node.name == WILDCARD && cmd.depth == node.depth {
+                    wildcard = Some((child, Node { name: cmd.cmd.clone(), ..node }));
+                }
+            }
+
+            if matched.is_none() && wildcard.is_none() && root != validation_tree.root {
+                matched = self.global(&cmd).map(|child| (child, Node::from_id(&child, &validation_tree.arena)));
+            }
+
+            // cmd did not match any node in the tree; end prematurely
+            let (child, node) = match matched.or(wildcard) {
+                Some(m) => m,
+                None => break,
+            };
+
+            // The word after a command that takes an argument is its value. A
+            // missing or mistyped value leaves the command itself unmatched.
+            let value = match &node.arg {
+                Some(arg) => match cmds.next() {
+                    Some(value) if arg.accepts(&value.cmd) => Some(Node::new(value.cmd.clone(), "", node.depth)),
+                    _ => break,
+                },
+                None => None,
+            };
+
+            // Build up the sequence tree so we can return it later
+            for node in std::iter::once(node).chain(value) {
+                seq_tree.root.append(
+                    Node::from_node_to_id(node, &mut seq_tree.arena), 
+                    &mut seq_tree.arena
+                );
+            }
+            
+            // Update root so next iterations begins from the subtree
+            root = child;
+        }
+
+        // On success, root has become a leaf
+        (seq_tree, validation_tree.node_ref(root))
+    }
+
+    /// The global command `cmd` names, if any, see `build_subtree`.
+    fn global(&self, cmd: &CliCmd) -> Option<NodeId> {
+        let tree = &self.config.valid_cmds;
+
+        tree.root.children(&tree.arena).find(|child| {
+            let node = tree.arena[*child].get();
+            matches!(node.depth, Depth::Any) && node.name != WILDCARD && cmd.matches(node, self.config.case_insensitive)
+        })
+    }
+
+    fn print_usage<W: Write>(&self, usage: &UsageError, writer: &mut W) -> io::Result<()> {
+        match &usage.unmatched {
+            Some(unmatched) => writeln!(writer, "Unrecognized command '{}'", unmatched)?,
+            None if !usage.matched.is_empty() => {
+                let names: Vec<&str> = usage.matched.iter().map(|node| &*node.name).collect();
+                writeln!(writer, "Incomplete command '{}'", names.join(&self.config.delimiter.to_string()))?;
+            }
+            None => {}
+        }
+        self.print_options(usage, writer)?;
+
+        // The first word that didn't match is the one worth correcting
+        if let Some(unmatched) = usage.unmatched.as_deref() {
+            let candidates: Vec<&str> = usage.expected.iter().map(|node| &*node.name).collect();
+            if let Some(suggestion) = translator::closest(unmatched, &candidates).filter(|s| *s != unmatched) {
+                writeln!(writer, "did you mean '{}'?", suggestion)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The `Usage:` line and the commands that could follow what matched.
+    fn print_options<W: Write>(&self, usage: &UsageError, writer: &mut W) -> io::Result<()> {
+        write!(writer, "Usage: ")?;
+        
+        for node in &usage.matched {
+            write!(writer, "{}{}", node.name, self.config.delimiter)?;
+        }
+
+        write!(writer, "<cmd>\nWhere 'cmd' can be either of\n")?;
+
+        self.print_children(&usage.last_valid_node, writer)
+    }
+
+    /// Children of `node` that may be shown to the user, i.e. not hidden.
+    /// None if `node` isn't in the tree, such as a stale id in a `UsageError`.
+    fn listed_children(&self, node: &NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let arena = &self.config.valid_cmds.arena;
+        let children = arena.get(*node).map(|_| node.children(arena));
+
+        children
+            .into_iter()
+            .flatten()
+            .filter(move |child| Node::try_from_id(child, arena).is_some_and(|node| !node.hidden))
+    }
+
+    fn print_children<W: Write>(&self, node: &NodeId, writer: &mut W) -> io::Result<()> {
+        for child in self.listed_children(node) {
+            write!(writer, "\t* ")?;
+            self.print_node(&child, writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn print_node<W: Write>(&self, node: &NodeId, writer: &mut W) -> io::Result<()> {
+        let node = match Node::try_from_id(node, &self.config.valid_cmds.arena) {
+            Some(node) => node,
+            None => return Ok(()),
+        };
+        let (start, end) = self.style(CYAN);
+        write!(writer, "{}{}{}", start, node.name, end)?;
+
+        if let Some(arg) = node.arg {
+            write!(writer, " {}", arg)?;
+        }
+
+        if let Some(exp) = node.explanation {
+            let (start, end) = self.style(GRAY);
+            write!(writer, ": {}{}{}", start, exp, end)?;
+        }
+
+        writeln!(writer)
+    }
+}
+
+/// Lets one writer stand in for both of a session's streams.
+struct Shared<'w, W>(&'w RefCell<W>);
+
+impl<W: Write> Write for Shared<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Splits `input` into words at `delim`. A word may be quoted, or partly
+/// quoted, to keep delimiters in it, and `\"` stands for a quote inside
+/// quotes. Repeated delimiters don't make empty words, but `""` does.
+fn tokenize(input: &str, delim: char) -> Result<Vec<Cow<'_, str>>, InputError<'_>> {
+    let mut words = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c == delim {
+            chars.next();
+            continue;
+        }
+
+        // Words without quotes are borrowed as they are
+        let mut word: Option<String> = None;
+        let mut end = start;
+
+        while let Some((i, c)) = chars.next() {
+            if c == delim {
+                break;
+            } else if c != '"' {
+                match word.as_mut() {
+                    Some(word) => word.push(c),
+                    None => end = i + c.len_utf8(),
+                }
+                continue;
+            }
+
+            let word = word.get_or_insert_with(|| input[start..end].to_string());
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) if matches!(chars.peek(), Some((_, '"')) | Some((_, '\\'))) => {
+                        word.extend(chars.next().map(|(_, c)| c));
+                    }
+                    Some((_, c)) => word.push(c),
+                    None => return Err(InputError::UnterminatedQuote(&input[i..])),
+                }
+            }
+        }
+
+        words.push(match word {
+            Some(word) => Cow::Owned(word),
+            None => Cow::Borrowed(&input[start..end]),
+        });
+    }
+
+    Ok(words)
+}
+
+/// Where the last word of `line` starts, and the names of the listed commands
+/// it can be completed to. The words before it are split and matched as
+/// `handle_input` does, from `root` or, after a leading `separator`, from the
+/// top of the tree.
+fn completions<'t>(
+    tree: &'t Tree,
+    root: NodeId,
+    delimiter: char,
+    separator: char,
+    case_insensitive: bool,
+    line: &str,
+) -> (usize, Vec<&'t str>) {
+    let arena = &tree.arena;
+    let input = line.trim_start();
+    let (mut root, input, absolute) = match input.strip_prefix(separator) {
+        Some(input) => (tree.root, input, true),
+        None => (root, input, false),
+    };
+
+    // A path typed as the first word is completed a segment at a time
+    let last_delimiter = input.rfind(delimiter);
+    let split = match last_delimiter {
+        Some(i) => i + delimiter.len_utf8(),
+        None if absolute => input.rfind(separator).map_or(0, |i| i + separator.len_utf8()),
+        None => 0,
+    };
+    let (words, partial) = input.split_at(split);
+    let start = line.len() - partial.len();
+
+    let clicmds = match Cli::construct_clicmds(words, delimiter) {
+        Ok(clicmds) if absolute => Cli::split_path(clicmds, separator),
+        Ok(clicmds) => clicmds,
+        Err(_) => return (start, vec![]),
+    };
+
+    // Walk down to the node whose children the last word is completed against
+    for clicmd in clicmds {
+        let cmd = CliCmd {
+            cmd: clicmd.cmd,
+            depth: match Node::from_id(&root, arena).depth {
+                Depth::Some(d) => Depth::Some(d + 1),
+                Depth::Any => Depth::Any,
+            },
+        };
+        match root.children(arena).find(|c| cmd.matches(&Node::from_id(c, arena), case_insensitive)) {
+            Some(child) => root = child,
+            None => return (start, vec![]),
+        }
+    }
+
+    let candidates = root.children(arena)
+        .filter_map(|c| arena.get(c).and_then(|node| node.try_get()))
+        .filter(|node| {
+            let typed = node.name.get(..partial.len());
+            !node.hidden && typed.is_some_and(|typed| {
+                if case_insensitive { typed.eq_ignore_ascii_case(partial) } else { typed == partial }
+            })
+        })
+        .map(|node| &*node.name)
+        .collect();
+
+    (start, candidates)
+}
+
+/// The `dispatch_with` handler and the thread it runs on. Every command
+/// handed to it is numbered, so that it can be told apart from one that
+/// timed out before it.
+struct Dispatcher {
+    commands: Sender<(usize, Vec<String>)>,
+    done: Receiver<usize>,
+    next: usize,
+}
+
+impl Dispatcher {
+    fn spawn(mut handler: impl FnMut(&[&str]) + Send + 'static) -> Dispatcher {
+        let (commands, received) = mpsc::channel::<(usize, Vec<String>)>();
+        let (finished, done) = mpsc::channel();
+
+        // Ends once the CLI is dropped and nothing is left to run
+        thread::spawn(move || {
+            for (id, path) in received {
+                handler(&path.iter().map(String::as_str).collect::<Vec<_>>());
+                if finished.send(id).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Dispatcher { commands, done, next: 0 }
+    }
+
+    /// Runs the handler on `path`. False if it didn't return within `timeout`,
+    /// or can't run at all because it panicked before.
+    fn run(&mut self, path: &[&str], timeout: Option<Duration>) -> bool {
+        let id = self.next;
+        self.next += 1;
+
+        let path = path.iter().map(|name| name.to_string()).collect();
+        self.commands.send((id, path)).is_ok() && check_timeout(&self.done, id, timeout)
+    }
+}
+
+/// Waits up to `timeout`, if there is one, for `done` to report that command
+/// `id` is done. Commands before it that timed out may report first.
+fn check_timeout(done: &Receiver<usize>, id: usize, timeout: Option<Duration>) -> bool {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        let finished = match deadline {
+            Some(deadline) => done.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => done.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match finished {
+            Ok(finished) if finished < id => continue,
+            Ok(_) => return true,
+            Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use yaml_rust::YamlLoader;
+    use translator::yaml;
+
+    const YAMLDOC: &str =
+    "
+    sat:
+    - obc:
+      - ping
+      - set
+    - adcs:
+      - ping
+      - set
+    - pay:
+      - ping
+      - take_pic
+    
+    gs:
+    - radio:
+      - ping
+      - set_freq
+    - sys:
+      - config
+    ";
+
+    fn get_cli<'a>(yaml: &'a yaml_rust::Yaml) -> Cli<'a> {
+        let cmd_tree = yaml::to_tree(yaml).unwrap();
+        
+        let config = CliConfig::new(
+            "$: ", 
+            cmd_tree,
+        ).expect("Invalid configuration");
+    
+        Cli::open(config)
+    }
+
+    mod cd {
+        use super::*;
+
+        #[test]
+        fn should_change_root() {
+            assert!(Cli::should_change_root("cd"));
+            assert!(!Cli::should_change_root("c"));
+            assert!(!Cli::should_change_root("d"));
+            assert!(!Cli::should_change_root(""));
+            assert!(Cli::should_change_root("cd -"));
+            assert!(Cli::should_change_root("cd ."));
+            assert!(Cli::should_change_root("cd .."));
+            assert!(Cli::should_change_root("cd sat"));
+            assert!(Cli::should_change_root("cd /sat"));
+            assert!(Cli::should_change_root("cd /.."));
+            assert!(Cli::should_change_root("cd \t"));
+            assert!(Cli::should_change_root("cd\n"));
+            assert!(!Cli::should_change_root("cdfoo"));
+            assert!(!Cli::should_change_root("cdr\n"));
+            assert!(!Cli::should_change_root("cd-"));
+            assert!(!Cli::should_change_root("cd/sat"));
+        }
+
+        #[test]
+        fn change_root_normal() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+            let arena = &cli.config.valid_cmds.arena;
+
+            let (node, prompt) = cli.change_root("cd sat").unwrap();
+            assert_eq!(prompt, "sat");
+            assert_eq!(
+                Node::from_id(&node, arena),
+                Node::new("sat", "", Depth::Some(1))
+            );
+
+            let (node, prompt) = cli.change_root("cd -").unwrap();
+            assert_eq!(prompt, "");
+            assert_eq!(
+                Node::from_id(&node, arena),
+                Node::new(translator::ROOT_NAME, "", Depth::Some(0))
+            );
+        }
+
+        #[test]
+        fn change_root_noop() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let root = cli.config.valid_cmds.root;
+
+            for input in ["cd .", "cd ", "cd \t"] {
+                assert_eq!(cli.change_root(input).unwrap(), (root, String::new()));
+            }
+
+            let (sat, prompt) = cli.change_root("cd sat").unwrap();
+            cli.current_root = sat;
+            cli.current_prompt = prompt;
+            assert_eq!(cli.change_root("cd .").unwrap(), (sat, "sat".to_string()));
+            assert_eq!(cli.change_root("cd ./").unwrap(), (sat, "sat".to_string()));
+
+            // Whitespace alone stays put, like cd .
+            for input in ["cd ", "cd \t"] {
+                assert_eq!(cli.change_root(input).unwrap(), (sat, "sat".to_string()));
+            }
+
+            // A bare cd still goes to the root
+            for input in ["cd", "cd\n"] {
+                assert_eq!(cli.change_root(input).unwrap(), (root, String::new()));
+            }
+
+            cli.run_with(Cursor::new("cd \ncd   \n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(cli.current_prompt, "sat");
+            cli.run_with(Cursor::new("cd\n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(cli.current_root, root);
+        }
+
+        #[test]
+        fn change_root_unchanged() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+
+            cli.run_with(Cursor::new("cd gs\ncd /sat/obc\ncd .\ncd ../obc\n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(cli.current_prompt, "sat/obc");
+            assert_eq!(cli.visited.len(), 2);
+
+            cli.run_with(Cursor::new("cd -\n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(cli.current_prompt, "gs");
+        }
+
+        #[test]
+        fn change_root_prefix() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+
+            let (_, prompt) = cli.change_root("cd sat/ob").unwrap();
+            assert_eq!(prompt, "sat/obc");
+            let (_, prompt) = cli.change_root("cd g/r").unwrap();
+            assert_eq!(prompt, "gs/radio");
+
+            let (radio, _) = cli.change_root("cd gs/radio").unwrap();
+            cli.current_root = radio;
+            let (_, prompt) = cli.change_root("cd ../../sat/a").unwrap();
+            assert_eq!(prompt, "sat/adcs");
+            assert!(matches!(cli.change_root("cd ../x"), Err(CliError::InvalidPath("../x"))));
+        }
+
+        const PREFIXES: &str = "sat:\n- obc:\n  - ping\n- ocs:\n  - ping\n- oc:\n  - ping\n- pay:\n  - ping";
+
+        #[test]
+        fn change_root_ambiguous() {
+            let yaml = YamlLoader::load_from_str(PREFIXES).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            match cli.change_root("cd sat/o") {
+                Err(CliError::AmbiguousPath { path, candidates }) => {
+                    assert_eq!(path, "sat/o");
+                    assert_eq!(candidates, vec!["obc", "ocs", "oc"]);
+                }
+                other => panic!("expected an ambiguous path, got {:?}", other),
+            }
+
+            // An exact name wins over the longer ones it starts
+            let (_, prompt) = cli.change_root("cd sat/oc").unwrap();
+            assert_eq!(prompt, "sat/oc");
+            let (_, prompt) = cli.change_root("cd sat/ocs").unwrap();
+            assert_eq!(prompt, "sat/ocs");
+        }
+
+        #[test]
+        fn prefix_session() {
+            let yaml = YamlLoader::load_from_str(PREFIXES).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd s\ncd ob\ncd ../o\ncd ../x\ncd /s/p\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            assert!(output.contains("sat/obc$: Ambiguous path: ../o\nobc  ocs  oc\n"));
+            assert!(output.contains("sat/obc$: No such path: ../x\n"));
+            assert_eq!(cli.current_prompt, "sat/pay");
+        }
+
+        #[test]
+        fn change_root_previous() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd sat\ncd /gs\ncd -\n".as_bytes()), &mut output).unwrap();
+            assert_eq!(cli.current_prompt, "sat");
+            assert_eq!(
+                Node::from_id(&cli.current_root, &cli.config.valid_cmds.arena),
+                Node::new("sat", "", Depth::Some(1))
+            );
+
+            // Going back again toggles to where we just were, as often as we like
+            let sat = cli.current_root;
+            let gs = cli.config.valid_cmds.find_by_path(&["gs"]).unwrap();
+            for expected in [gs, sat, gs] {
+                cli.run_with(Cursor::new("cd -\n".as_bytes()), &mut output).unwrap();
+                assert_eq!(cli.current_root, expected);
+            }
+            assert_eq!(cli.current_prompt, "gs");
+        }
+
+        #[test]
+        fn back() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut prompts = Vec::new();
+
+            cli.run_with(Cursor::new("cd sat\ncd obc\ncd /gs/radio\n".as_bytes()), io::sink()).unwrap();
+            for _ in 0..3 {
+                cli.run_with(Cursor::new("back\n".as_bytes()), io::sink()).unwrap();
+                prompts.push(cli.current_prompt.clone());
+            }
+            assert_eq!(prompts, vec!["sat/obc", "sat", ""]);
+
+            let mut output = Vec::new();
+            cli.run_with(Cursor::new("back\n".as_bytes()), &mut output).unwrap();
+            assert!(String::from_utf8(output).unwrap().contains("back: nowhere to go back to"));
+
+            // cd - undoes the last back, and is itself something to go back from
+            cli.run_with(Cursor::new("cd -\n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(cli.current_prompt, "sat");
+            cli.run_with(Cursor::new("back\n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(cli.current_prompt, "");
+        }
+
+        #[test]
+        fn change_root_up() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let (node, _) = cli.change_root("cd sat/obc").unwrap();
+            cli.current_root = node;
+
+            let (node, prompt) = cli.change_root("cd ..").unwrap();
+            assert_eq!(prompt, "sat");
+            assert_eq!(
+                Node::from_id(&node, &cli.config.valid_cmds.arena),
+                Node::new("sat", "", Depth::Some(1))
+            );
+
+            let (node, prompt) = cli.change_root("cd ../..").unwrap();
+            assert_eq!(prompt, "");
+            assert_eq!(node, cli.config.valid_cmds.root);
+
+            // More '..' than ancestors stops at the root
+            let (node, prompt) = cli.change_root("cd ../../../..").unwrap();
+            assert_eq!(prompt, "");
+            assert_eq!(node, cli.config.valid_cmds.root);
+
+            let (_, prompt) = cli.change_root("cd ../pay").unwrap();
+            assert_eq!(prompt, "sat/pay");
+
+            let (_, prompt) = cli.change_root("cd ../../../gs/radio/").unwrap();
+            assert_eq!(prompt, "gs/radio");
+
+            let (_, prompt) = cli.change_root("cd /gs/../sat/./adcs").unwrap();
+            assert_eq!(prompt, "sat/adcs");
+        }
+
+        #[test]
+        fn change_root_leaf() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            assert!(matches!(cli.change_root("cd sat/obc/ping"), Err(CliError::NotAGroup("sat/obc/ping"))));
+            assert!(matches!(cli.change_root("cd /gs/sys/config"), Err(CliError::NotAGroup("/gs/sys/config"))));
+            assert!(cli.change_root("cd sat obc ping").is_err());
+
+            cli.run_with(Cursor::new("cd sat\ncd obc/ping\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("sat$: Not a command group: obc/ping\n"));
+            assert_eq!(cli.current_prompt, "sat");
+            assert_eq!(cli.current_root, cli.config.valid_cmds.find_by_path(&["sat"]).unwrap());
+        }
+
+        #[test]
+        fn change_root_relative_invalid() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let (node, prompt) = cli.change_root("cd sat").unwrap();
+            cli.current_root = node;
+            cli.current_prompt = prompt;
+
+            assert!(matches!(
+                cli.change_root("cd ../foo"),
+                Err(CliError::InvalidPath("../foo"))
+            ));
+            assert!(matches!(
+                cli.change_root("cd gs"),
+                Err(CliError::InvalidPath("gs"))
+            ));
+
+            // A path that matches partway doesn't descend partway either
+            let mut output = Vec::new();
+            cli.run_with(Cursor::new("cd obc/foo\ncd doesnotexist\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("sat$: No such path: obc/foo\n"));
+            assert!(output.contains("sat$: No such path: doesnotexist\n"));
+            assert_eq!(cli.current_root, node);
+            assert_eq!(cli.current_prompt, "sat");
+        }
+
+        #[test]
+        fn change_root_absolute() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let arena = &cli.config.valid_cmds.arena;
+
+            let (node, prompt) = cli.change_root("cd /gs/radio").unwrap();
+            assert_eq!(prompt, "gs/radio");
+            assert_eq!(
+                Node::from_id(&node, arena),
+                Node::new("radio", "", Depth::Some(2))
+            );
+
+            // Absolute paths ignore the current root
+            cli.current_root = node;
+            let (node, prompt) = cli.change_root("cd /sat/obc/").unwrap();
+            assert_eq!(prompt, "sat/obc");
+            assert_eq!(
+                Node::from_id(&node, &cli.config.valid_cmds.arena),
+                Node::new("obc", "", Depth::Some(2))
+            );
+
+            let (node, prompt) = cli.change_root("cd /").unwrap();
+            assert_eq!(prompt, "");
+            assert_eq!(node, cli.config.valid_cmds.root);
+        }
+
+        #[test]
+        fn change_root_absolute_invalid() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            assert!(matches!(
+                cli.change_root("cd /sat/foo"),
+                Err(CliError::InvalidPath("/sat/foo"))
+            ));
+
+            cli.run_with(Cursor::new("cd /gs\ncd /gs/foo/sys\nexit\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("No such path: /gs/foo/sys"));
+            assert_eq!(cli.current_prompt, "gs");
+            assert_eq!(cli.current_root, cli.config.valid_cmds.find_by_path(&["gs"]).unwrap());
+        }
+
+        #[test]
+        fn absolute_command() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            let script = "cd gs/radio\n/sat/obc/ping\n/sat obc set\n/sat/adcs ping\nping\n/sat/obc\n/sat/foo/ping\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            let radio = cli.config.valid_cmds.find_by_path(&["gs", "radio"]).unwrap();
+            assert_eq!(cli.current_root, radio);
+            assert_eq!(cli.current_prompt, "gs/radio");
+            drop(cli);
+
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(accepted, vec!["sat obc ping", "sat obc set", "sat adcs ping", "ping"]);
+            assert!(output.contains("gs/radio$: USAGE\nIncomplete command 'sat obc'\nUsage: sat obc <cmd>\n"));
+            assert!(output.contains("gs/radio$: USAGE\nUnrecognized command 'foo'\nUsage: sat <cmd>\n"));
+        }
+    }
+
+    mod run {
+        use super::*;
+
+        pub(super) fn run_script(script: &str) -> String {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn prompt_and_exit() {
+            assert_eq!(run_script("exit\n"), "$: \n");
+            assert_eq!(run_script(""), "$: \n");
+            assert_eq!(run_script("\nquit\n"), "$: $: \n");
+        }
+
+        #[test]
+        fn prompt_format() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .prompt_format("gs[{path}]> ");
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd sat/obc\nexit\n".as_bytes()), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "gs[]> gs[sat/obc]> \n");
+        }
+
+        #[test]
+        fn editing() {
+            let accepted = |script: &str| run_script(script).matches("ACCEPTED").count();
+
+            // Cursor left four times, then insert the missing word
+            assert_eq!(accepted("sat ping\x1b[D\x1b[D\x1b[D\x1b[Dobc \n"), 1);
+            assert_eq!(accepted("obc ping\x01sat \n"), 1);
+            assert_eq!(accepted("sat obc pnig\x17ping\n"), 1);
+            assert_eq!(accepted("nonsense\x15sat obc ping\n"), 1);
+            assert_eq!(accepted("sat obc pig\x1b[D\x7fin\x05\n"), 1);
+        }
+
+        #[test]
+        fn interrupt() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .catch_interrupt(true);
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            // The second Ctrl-C, on an empty line, ends the session
+            cli.run_with(Cursor::new("sat ob\x03sat obc ping\n\x03sat obc ping\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.matches("ACCEPTED").count(), 1);
+            assert!(!output.contains("USAGE"));
+
+            // Otherwise Ctrl-C is just another character
+            assert!(run_script("sat obc ping\x03\n").contains("USAGE"));
+        }
+
+        struct BrokenPipe;
+
+        impl Write for BrokenPipe {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn exit_cmds() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .exit_cmds(vec!["bye", "logout"]);
+            let cli = Cli::open(config);
+
+            assert!(cli.should_exit("bye"));
+            assert!(cli.should_exit(Cli::normalize("  logout \n")));
+            assert!(!cli.should_exit("quit"));
+            assert!(!cli.should_exit("exit"));
+            assert!(!cli.should_exit(""));
+        }
+
+        #[test]
+        fn end_of_input() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            cli.interactive = true;
+
+            let session = |cli: &mut Cli, script: &str| {
+                let mut output = Vec::new();
+                cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+                String::from_utf8(output).unwrap()
+            };
+
+            // The terminal already moved past the typed exit command
+            assert_eq!(session(&mut cli, "exit\n"), "$: ");
+            assert_eq!(session(&mut cli, ""), "$: logout\n");
+
+            // A pipe shows neither, so the prompt line still needs ending
+            cli.interactive = false;
+            assert_eq!(session(&mut cli, "exit\n"), "$: \n");
+            assert_eq!(session(&mut cli, ""), "$: \n");
+        }
+
+        #[test]
+        fn line_endings() {
+            assert_eq!(run_script("exit\r\n"), run_script("exit\n"));
+            assert_eq!(run_script("\r\n  \nexit\r\n"), run_script("\n\nexit\n"));
+            assert_eq!(run_script("cd sat/obc\nping  \n"), run_script("cd sat/obc\nping\n"));
+            assert_eq!(run_script("sat obc ping\r\n"), run_script("sat obc ping\n"));
+            assert!(run_script("cd sat/obc\nping  \n").contains("ACCEPTED"));
+        }
+
+        #[test]
+        fn io_error() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+
+            let result = cli.run_with(Cursor::new("exit\n".as_bytes()), BrokenPipe);
+            assert!(matches!(result, Err(CliError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe));
+        }
+
+        #[test]
+        fn split_streams() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = Cli::open(CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap()).unwrap());
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+
+            cli.run_with_streams(Cursor::new("sat obc ping\nsat obc\ncd nowhere\n".as_bytes()), &mut out, &mut err)
+                .unwrap();
+            let (out, err) = (String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap());
+
+            assert_eq!(out, "ACCEPTED\n");
+            assert!(err.starts_with("$: "));
+            assert!(err.contains("USAGE"));
+            assert!(err.contains("nowhere"));
+            assert!(!err.contains("ACCEPTED"));
+        }
+
+        #[test]
+        fn accepted() {
+            let output = run_script("sat obc ping\nexit\n");
+            assert!(output.contains("ACCEPTED"));
+        }
+
+        #[test]
+        fn unknown_or_incomplete() {
+            let output = run_script("sat foo\n");
+            assert!(output.contains("USAGE\nUnrecognized command 'foo'\nUsage: sat <cmd>\n"));
+            assert!(!output.contains("Incomplete"));
+
+            let output = run_script("sat obc\n");
+            assert!(output.contains("USAGE\nIncomplete command 'sat obc'\nUsage: sat obc <cmd>\n"));
+            assert!(!output.contains("Unrecognized"));
+        }
+
+        #[test]
+        fn quiet_by_default() {
+            assert_eq!(
+                run_script("sat obc ping\ncd sat\nobc\n"),
+                "$: ACCEPTED\n$: sat$: USAGE\nIncomplete command 'obc'\nUsage: obc <cmd>\nWhere 'cmd' can be either of\n\t* ping\n\t* set\nsat$: \n"
+            );
+        }
+
+        #[test]
+        fn usage() {
+            let output = run_script("sat obc\nexit\n");
+            assert!(output.contains("USAGE"));
+            assert!(output.contains("\t* ping"));
+            assert!(output.contains("\t* set"));
+            assert!(!output.contains("Unrecognized"));
+        }
+
+        #[test]
+        fn unrecognized() {
+            let output = run_script("sat obc flarb\nsat obc ping now\n");
+            assert!(output.contains("USAGE\nUnrecognized command 'flarb'\nUsage: sat obc <cmd>\n"));
+            assert!(output.contains("USAGE\nUnrecognized command 'now'\nUsage: sat obc ping <cmd>\n"));
+        }
+
+        #[test]
+        fn grafted_tree() {
+            let mut tree = Tree::new_depth(3);
+            tree.insert_at_path(&["sat", "obc"], Node::new("ping", "", 0));
+            tree.insert_at_path(&["sat", "obc"], Node::new("set", "", 0));
+
+            let mut cli = Cli::open(CliConfig::new("$: ", tree).unwrap());
+            let mut output = Vec::new();
+            cli.run_with(Cursor::new("sat obc ping\ncd sat\nobc set\nobc\n".as_bytes()), &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.matches("ACCEPTED").count(), 2);
+            assert!(output.contains("Usage: obc <cmd>\n"));
+        }
+    }
+
+    mod complete {
+        use super::*;
+
+        #[test]
+        fn candidates() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            assert_eq!(cli.complete("s"), (0, vec!["sat"]));
+            assert_eq!(cli.complete(""), (0, vec!["sat", "gs"]));
+            assert_eq!(cli.complete("sat obc "), (8, vec!["ping", "set"]));
+            assert_eq!(cli.complete("gs radio set"), (9, vec!["set_freq"]));
+            assert!(cli.complete("sat foo p").1.is_empty());
+        }
+
+        #[test]
+        fn like_input() {
+            let yaml = YamlLoader::load_from_str("sat:\n- obc:\n    aliases: [cpu]\n    children: [ping, set]").unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .case_insensitive(true);
+            let cli = Cli::open(config);
+
+            assert_eq!(cli.complete("sat  ob"), (5, vec!["obc"]));
+            assert_eq!(cli.complete(" sat cpu p"), (9, vec!["ping"]));
+            assert_eq!(cli.complete("SAT Obc S"), (8, vec!["set"]));
+            assert_eq!(cli.complete("/sat o"), (5, vec!["obc"]));
+            assert_eq!(cli.complete("/sat/obc/p"), (9, vec!["ping"]));
+            assert!(cli.complete("sat/obc/p").1.is_empty());
+        }
+
+        #[test]
+        fn from_current_root() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let (root, _) = cli.change_root("cd sat").unwrap();
+            cli.current_root = root;
+
+            assert_eq!(cli.complete("o"), (0, vec!["obc"]));
+            assert_eq!(cli.complete("pay t"), (4, vec!["take_pic"]));
+            assert_eq!(cli.complete("/gs r"), (4, vec!["radio"]));
+        }
+
+        #[test]
+        fn tab() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat  ob\t pi\t\n/sat/o\t/pi\t\nexit\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.matches("ACCEPTED").count(), 2);
+        }
+
+        #[test]
+        fn tab_ambiguous() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat obc \t\nexit\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("ping  set\n$: sat obc "));
+        }
+    }
+
+    mod history {
+        use super::*;
+
+        #[test]
+        fn recorded() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat obc ping\n\n  \ngs\nquit\n".as_bytes()), &mut output).unwrap();
+            assert_eq!(cli.history, vec!["sat obc ping", "gs"]);
+        }
+
+        #[test]
+        fn capped() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .history_size(2);
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat\ngs\nsat obc\n".as_bytes()), &mut output).unwrap();
+            assert_eq!(cli.history, vec!["gs", "sat obc"]);
+        }
+
+        #[test]
+        fn arrows() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            // Up twice lands on the first command, down once on the second
+            let script = "sat obc ping\ngs\n\x1b[A\x1b[A\x1b[B\n\x1b[A\x1b[A\x1b[A\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            assert_eq!(cli.history, vec!["sat obc ping", "gs", "sat obc ping"]);
+        }
+
+        #[test]
+        fn repeats() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat\nsat\ngs\nsat\nsat\n".as_bytes()), &mut output).unwrap();
+            assert_eq!(cli.history, vec!["sat", "gs", "sat"]);
+        }
+
+        #[test]
+        fn file() {
+            let path = std::env::temp_dir().join(format!("gs-cli-history-{}", std::process::id()));
+            let path = path.to_str().unwrap();
+            fs::write(path, "sat\n\ngs\n").unwrap();
+
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .history_file(path);
+            let mut cli = Cli::open(config);
+            assert_eq!(cli.history, vec!["sat", "gs"]);
+
+            let mut output = Vec::new();
+            cli.run_with(Cursor::new("sat obc\nsat obc\nexit\n".as_bytes()), &mut output).unwrap();
+
+            let saved = fs::read_to_string(path).unwrap();
+            assert_eq!(saved, "sat\n\ngs\nsat obc\n");
+
+            // A new session picks up where the last one left off
+            let cli = Cli::open(CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap()).unwrap().history_file(path));
+            fs::remove_file(path).unwrap();
+            assert_eq!(cli.history, vec!["sat", "gs", "sat obc"]);
+        }
+
+        #[test]
+        fn file_repeats() {
+            let path = std::env::temp_dir().join(format!("gs-cli-history-repeats-{}", std::process::id()));
+            let path = path.to_str().unwrap();
+            fs::write(path, "sat\nsat\n\nsat\ngs\nsat\nsat\n").unwrap();
+
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = Cli::open(CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap()).unwrap().history_file(path));
+            fs::remove_file(path).unwrap();
+            assert_eq!(cli.history, vec!["sat", "gs", "sat"]);
+        }
+    }
+
+    mod state {
+        use super::*;
+
+        fn open<'p>(path: &'p str, yaml: &'p yaml_rust::Yaml) -> Cli<'p> {
+            Cli::open(CliConfig::new("$: ", yaml::to_tree(yaml).unwrap()).unwrap().state_file(path))
+        }
+
+        #[test]
+        fn round_trip() {
+            let path = std::env::temp_dir().join(format!("gs-cli-state-{}", std::process::id()));
+            let path = path.to_str().unwrap();
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+
+            let mut cli = open(path, &yaml[0]);
+            cli.run_with(Cursor::new("cd sat/obc\n".as_bytes()), io::sink()).unwrap();
+            assert_eq!(fs::read_to_string(path).unwrap(), "/sat/obc\n");
+
+            let cli = open(path, &yaml[0]);
+            assert_eq!(cli.current_root, cli.config.valid_cmds.find_by_path(&["sat", "obc"]).unwrap());
+            assert_eq!(cli.current_prompt, "sat/obc");
+
+            // The saved place is gone from a changed tree
+            let changed = YamlLoader::load_from_str("sat:\n- pay:\n  - take_pic").unwrap();
+            let mut cli = open(path, &changed[0]);
+            let mut output = Vec::new();
+            cli.run_with(Cursor::new("exit\n".as_bytes()), &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with("warning: /sat/obc no longer exists, starting at /\n$: "));
+            assert_eq!(fs::read_to_string(path).unwrap(), "/\n");
+            fs::remove_file(path).unwrap();
+
+            // Without a saved file we start at the root
+            let cli = open(path, &yaml[0]);
+            assert_eq!(cli.current_root, cli.config.valid_cmds.root);
+        }
+    }
+
+    mod help {
+        use super::*;
+
+        const EXPLAINED: &str =
+        "
+        sat:
+        - obc:
+          - ping:
+            'Ping the obc'
+          - set
+        ";
+
+        fn run_script(yaml: &str, script: &str) -> String {
+            let yaml = YamlLoader::load_from_str(yaml).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn should_help() {
+            assert!(Cli::should_help("help"));
+            assert!(Cli::should_help("help\n"));
+            assert!(Cli::should_help("help sat"));
+            assert!(!Cli::should_help("helpme"));
+            assert!(!Cli::should_help("hel"));
+        }
+
+        #[test]
+        fn children() {
+            let output = run_script(EXPLAINED, "cd sat/obc\nhelp\n");
+            assert!(output.contains("\t* ping: Ping the obc\n\t* set\n"));
+
+            let output = run_script(YAMLDOC, "help\n");
+            assert!(output.contains("\t* sat\n\t* gs\n"));
+        }
+
+        #[test]
+        fn single() {
+            let output = run_script(EXPLAINED, "cd sat\nhelp obc ping\n");
+            assert!(output.contains("sat$: ping: Ping the obc\n"));
+
+            let output = run_script(EXPLAINED, "help sat obc set\n");
+            assert!(output.contains("$: set\n"));
+
+            let output = run_script(EXPLAINED, "help sat foo\n");
+            assert!(output.contains("No such command: sat foo\n"));
+        }
+
+        #[test]
+        fn question_mark() {
+            let output = run_script(EXPLAINED, "sat obc?\n");
+            assert!(output.contains("$: Usage: sat obc <cmd>\nWhere 'cmd' can be either of\n\t* ping: Ping the obc\n\t* set\n"));
+            assert!(!output.contains("ACCEPTED"));
+            assert!(!output.contains("USAGE"));
+            assert!(!output.contains("Incomplete"));
+
+            let output = run_script(EXPLAINED, "cd sat\n?\nobc ping ?\nfoo?\n");
+            assert!(output.contains("sat$: \t* obc\n"));
+            assert!(output.contains("sat$: Usage: obc ping: Ping the obc\n"));
+            assert!(output.contains("sat$: Unrecognized command 'foo'\n"));
+            assert!(!output.contains("ACCEPTED"));
+        }
+    }
+
+    mod ls {
+        use super::*;
+
+        #[test]
+        fn should_list() {
+            assert!(Cli::should_list("ls"));
+            assert!(Cli::should_list("ls sat\n"));
+            assert!(!Cli::should_list("lsat"));
+        }
+
+        #[test]
+        fn children() {
+            let output = run::run_script("ls\ncd sat/obc\nls\n");
+            assert!(output.contains("$: sat/  gs/\n"));
+            assert!(output.contains("sat/obc$: ping  set\n"));
+        }
+
+        #[test]
+        fn subpath() {
+            let output = run::run_script("cd sat\nls pay\nls ../gs\nls /gs/sys\nls foo\n");
+            assert!(output.contains("sat$: ping  take_pic\n"));
+            assert!(output.contains("sat$: radio/  sys/\n"));
+            assert!(output.contains("sat$: config\n"));
+            assert!(output.contains("sat$: No such path: foo\n"));
+        }
+
+        #[test]
+        fn descriptions() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n  description: 'Satellite'\n  children:\n  - obc:\n      description: 'On-board computer'\n      children: [ping]\n  - pay: [take_pic]\ngs: 'Ground station'"
+            ).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("ls\nls sat\nhelp\nsat\n".as_bytes()), &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("$: sat/  Satellite\ngs    Ground station\n"));
+            assert!(output.contains("$: obc/  On-board computer\npay/\n"));
+            assert!(output.contains("$: \t* sat: Satellite\n\t* gs: Ground station\n"));
+            assert!(output.contains("Where 'cmd' can be either of\n\t* obc: On-board computer\n\t* pay\n"));
+        }
+    }
+
+    mod pwd {
+        use super::*;
+
+        #[test]
+        fn should_print_path() {
+            assert!(Cli::should_print_path("pwd"));
+            assert!(Cli::should_print_path(Cli::normalize(" pwd \n")));
+            assert!(!Cli::should_print_path("pwd sat"));
+            assert!(!Cli::should_print_path("pwdx"));
+        }
+
+        #[test]
+        fn path() {
+            let output = run::run_script("pwd\ncd sat/obc\npwd\ncd ..\npwd\n");
+            assert!(output.contains("$: /\n"));
+            assert!(output.contains("sat/obc$: /sat/obc\n"));
+            assert!(output.contains("sat$: /sat\n"));
+        }
+
+        #[test]
+        fn current_path() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            assert_eq!(cli.current_path(), "/");
+
+            let (node, _) = cli.change_root("cd gs/radio").unwrap();
+            cli.current_root = node;
+            assert_eq!(cli.current_path(), "/gs/radio");
+
+            let (node, _) = cli.change_root("cd ../sys").unwrap();
+            cli.current_root = node;
+            assert_eq!(cli.current_path(), "/gs/sys");
+        }
+
+        #[test]
+        fn named_root() {
+            let yaml = YamlLoader::load_from_str("root:\n- sys:\n  - ping").unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd root/sys\npwd\ncd ..\n".as_bytes()), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("root/sys$: /root/sys\n"));
+            assert!(output.contains("root$: "));
+        }
+    }
+
+    mod color {
+        use super::*;
+
+        fn run_colored(script: &str, color: bool) -> String {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.color = color;
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn disabled() {
+            let output = run_colored("cd sat\nhelp\n", false);
+            assert!(!output.contains('\x1b'));
+            assert!(output.contains("sat$: \t* obc\n"));
+        }
+
+        #[cfg(feature = "color")]
+        #[test]
+        fn enabled() {
+            let output = run_colored("cd sat\nhelp\n", true);
+            assert!(output.contains("\x1b[36msat\x1b[0m$: \t* \x1b[36mobc\x1b[0m\n"));
+        }
+
+        #[cfg(not(feature = "color"))]
+        #[test]
+        fn without_feature() {
+            assert_eq!(run_colored("cd sat\nhelp\n", true), run_colored("cd sat\nhelp\n", false));
+        }
+    }
+
+    mod accept {
+        use super::*;
+
+        #[test]
+        fn handler() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat obc ping\nsat obc\ngs radio set_freq\n".as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            assert_eq!(accepted, vec!["sat obc ping", "gs radio set_freq"]);
+        }
+
+        #[test]
+        fn timeouts() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n- obc:\n    timeout: 0.001\n    children:\n    - ping\n    - reboot:\n        timeout: 10\n- pay:\n  - ping"
+            ).unwrap();
+            let mut timeouts = Vec::new();
+
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .timeout(Duration::from_secs(1))
+                .on_accept(|_, timeout| timeouts.push(timeout));
+            let mut cli = Cli::open(config);
+
+            for input in ["sat obc ping", "sat obc reboot", "sat pay ping"] {
+                assert!(cli.report_input(input, &mut io::empty(), &mut io::sink(), &mut io::sink()).unwrap());
+            }
+            drop(cli);
+
+            assert_eq!(timeouts, vec![
+                Some(Duration::from_millis(1)),
+                Some(Duration::from_secs(10)),
+                Some(Duration::from_secs(1)),
+            ]);
+        }
+
+        #[test]
+        fn no_response() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n- obc:\n    timeout: 0.001\n    children: [ping]\n- pay:\n  - ping"
+            ).unwrap();
+            let dispatched = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+            let (answer, answered) = mpsc::channel::<()>();
+
+            let log = dispatched.clone();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .timeout(Duration::from_secs(10))
+                .dispatch_with(move |path| {
+                    // The obc hangs until the test lets it answer
+                    if path[1] == "obc" {
+                        answered.recv().unwrap();
+                    }
+                    log.lock().unwrap().push(path.join(" "));
+                });
+            let mut cli = Cli::open(config);
+            let mut err = Vec::new();
+
+            assert!(!cli.report_input("sat obc ping", &mut io::empty(), &mut io::sink(), &mut err).unwrap());
+            answer.send(()).unwrap();
+            assert!(cli.report_input("sat pay ping", &mut io::empty(), &mut io::sink(), &mut err).unwrap());
+
+            assert_eq!(String::from_utf8(err).unwrap(), "No response from 'sat obc ping' within 1ms\n");
+            assert_eq!(*dispatched.lock().unwrap(), vec!["sat obc ping", "sat pay ping"]);
+        }
+
+        #[test]
+        fn late_answers() {
+            let (finished, done) = mpsc::channel();
+            assert!(!check_timeout(&done, 0, Some(Duration::from_millis(1))));
+
+            // The late answer to the first command is skipped
+            finished.send(0).unwrap();
+            finished.send(1).unwrap();
+            assert!(check_timeout(&done, 1, Some(Duration::from_millis(1))));
+
+            drop(finished);
+            assert!(!check_timeout(&done, 2, None));
+        }
+
+        #[test]
+        fn quiet() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .print_accepted(false);
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat obc ping\n".as_bytes()), &mut output).unwrap();
+            assert!(!String::from_utf8(output).unwrap().contains("ACCEPTED"));
+        }
+
+        #[test]
+        fn dry_run() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .dry_run(true)
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            let script = "dryrun\nsat obc ping\nsat obc\ndryrun off\ngs radio ping\ndryrun on\nsat pay take_pic\ndryrun maybe\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+            let output = String::from_utf8(output).unwrap();
+
+            assert_eq!(accepted, vec!["gs radio ping"]);
+            assert!(output.contains("$: dryrun on\n"));
+            assert!(output.contains("$: Would execute sat obc ping\n"));
+            assert!(output.contains("$: Would execute sat pay take_pic\n"));
+            assert!(output.contains("Usage: dryrun [on|off]\n"));
+            assert_eq!(output.matches("ACCEPTED").count(), 1);
+        }
+
+        #[test]
+        fn confirm() {
+            let yaml = YamlLoader::load_from_str("sat:\n- reboot:\n    confirm: true\n- ping").unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            let script = "sat reboot\nn\nsat ping\nsat reboot\nY\nsat reboot\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+            let output = String::from_utf8(output).unwrap();
+
+            assert_eq!(accepted, vec!["sat ping", "sat reboot"]);
+            assert_eq!(output.matches("Are you sure? [y/N] ").count(), 3);
+            assert_eq!(output.matches("Cancelled\n").count(), 2);
+        }
+
+        struct Recorder<'r>(&'r mut Vec<String>);
+
+        impl CliObserver for Recorder<'_> {
+            fn on_prompt_change(&mut self, path: &str) {
+                self.0.push(format!("prompt {}", path));
+            }
+
+            fn on_accept(&mut self, path: &[&str]) {
+                self.0.push(format!("accept {}", path.join(" ")));
+            }
+
+            fn on_usage(&mut self, _last_valid: NodeId, options: &[Node]) {
+                let names: Vec<&str> = options.iter().map(|node| &*node.name).collect();
+                self.0.push(format!("usage {}", names.join(" ")));
+            }
+        }
+
+        #[test]
+        fn observer() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut events = Vec::new();
+
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .observer(Recorder(&mut events));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd sat/obc\nping\nset\nnope\nback\ncd sat/obc\n".as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            assert_eq!(events, vec![
+                "prompt sat/obc",
+                "accept ping",
+                "accept set",
+                "usage ping set",
+                "prompt ",
+                "prompt sat/obc",
+            ]);
+        }
+    }
+
+    mod quotes {
+        use super::*;
+
+        fn words(input: &str) -> Vec<String> {
+            tokenize(input, ' ').unwrap().into_iter().map(Cow::into_owned).collect()
+        }
+
+        #[test]
+        fn balanced() {
+            assert_eq!(words(r#"gs sys set_name "ground station 1""#), vec!["gs", "sys", "set_name", "ground station 1"]);
+            assert_eq!(words(r#"a "b  c"d e"#), vec!["a", "b  cd", "e"]);
+            assert_eq!(words(r#"a "" b"#), vec!["a", "", "b"]);
+            assert!(matches!(tokenize("sat  obc", ' ').unwrap()[1], Cow::Borrowed("obc")));
+        }
+
+        #[test]
+        fn escaped() {
+            assert_eq!(words(r#"say "a \"quoted\" word""#), vec!["say", r#"a "quoted" word"#]);
+            assert_eq!(words(r#""back\\slash" \n"#), vec![r"back\slash", r"\n"]);
+        }
+
+        #[test]
+        fn unterminated() {
+            let err = tokenize(r#"gs sys set_name "ground station"#, ' ').unwrap_err();
+            assert!(matches!(err, InputError::UnterminatedQuote(r#""ground station"#)));
+            assert_eq!(err.to_string(), r#"Unterminated quote: "ground station"#);
+            assert!(tokenize(r#"say "\""#, ' ').is_err());
+        }
+
+        #[test]
+        fn values() {
+            let yaml = YamlLoader::load_from_str("gs:\n- sys:\n  - set_name <name>").unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(
+                Cursor::new("gs sys set_name \"ground station 1\"\ngs sys set_name \"ground\n".as_bytes()),
+                &mut output,
+            ).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("$: ACCEPTED name=ground station 1\n"));
+            assert!(output.contains("$: Unterminated quote: \"ground\n"));
+        }
+    }
+
+    mod wildcard {
+        use super::*;
+
+        const WILDCARDS: &str =
+        "
+        gs:
+        - radio:
+          - set_freq:
+            - '*'
+          - mode:
+            - reset
+            - '*':
+              - now
+              - later
+        ";
+
+        #[test]
+        fn any_word() {
+            let yaml = YamlLoader::load_from_str(WILDCARDS).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            let script = "gs radio set_freq 433\ngs radio set_freq\ngs radio mode safe now\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            assert_eq!(accepted, vec!["gs radio set_freq 433", "gs radio mode safe now"]);
+        }
+
+        #[test]
+        fn precedence() {
+            let yaml = YamlLoader::load_from_str(WILDCARDS).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            // 'reset' is a leaf of its own rather than a value for the wildcard
+            let clicmds = Cli::construct_clicmds("gs radio mode reset", ' ').unwrap();
+            let (_, node) = cli.build_subtree(cli.current_root, &clicmds);
+            assert_eq!(
+                Node::from_id(&cli.config.valid_cmds.resolve(node), &cli.config.valid_cmds.arena),
+                Node::new("reset", "", Depth::Some(4))
+            );
+
+            let clicmds = Cli::construct_clicmds("gs radio mode resets", ' ').unwrap();
+            let (_, node) = cli.build_subtree(cli.current_root, &clicmds);
+            assert_eq!(
+                Node::from_id(&cli.config.valid_cmds.resolve(node), &cli.config.valid_cmds.arena),
+                Node::new("*", "", Depth::Some(4))
+            );
+        }
+
+        #[test]
+        fn any_depth() {
+            let mut tree = Tree::new();
+            let sat = Node::from_data_to_id("sat", "", Depth::Some(1), &mut tree.arena);
+            let any = Node::from_data_to_id(WILDCARD, "", Depth::Any, &mut tree.arena);
+            let ping = Node::from_data_to_id("ping", "", Depth::Any, &mut tree.arena);
+            tree.root.append(sat, &mut tree.arena);
+            sat.append(any, &mut tree.arena);
+            any.append(ping, &mut tree.arena);
+
+            let config = CliConfig::new("$: ", tree).unwrap();
+            let cli = Cli::open(config);
+
+            let clicmds = Cli::construct_clicmds("sat obc ping", ' ').unwrap();
+            let (seq_tree, node) = cli.build_subtree(cli.current_root, &clicmds);
+            assert_eq!(cli.config.valid_cmds.resolve(node), ping);
+            assert_eq!(translator::subtree_count(&seq_tree.root, &seq_tree.arena), 3);
+        }
+
+        #[test]
+        #[should_panic(expected = "belongs to another tree")]
+        fn leaf_in_sequence_tree() {
+            let yaml = YamlLoader::load_from_str(WILDCARDS).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            // The leaf is a validation tree node, so counting it in the sequence tree is a bug
+            let clicmds = Cli::construct_clicmds("gs radio mode reset", ' ').unwrap();
+            let (seq_tree, leaf) = cli.build_subtree(cli.current_root, &clicmds);
+            seq_tree.subtree_count(&seq_tree.resolve(leaf));
+        }
+
+        #[test]
+        fn after_cd() {
+            let output = run::run_script("cd sat\nobc ping\ncd obc\nset\n");
+            assert_eq!(output.matches("ACCEPTED").count(), 2);
+        }
+
+        #[test]
+        fn global() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut tree = yaml::to_tree(&yaml[0]).unwrap();
+            for name in ["status", "ping", "log"] {
+                let global = tree.insert_at_path(&[], Node::new(name, "", 0));
+                tree.arena[global].get_mut().depth = Depth::Any;
+            }
+            tree.insert_at_path(&["log"], Node::new("dump", "", 0));
+
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", tree)
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let script = "status\ncd sat\nstatus\ncd obc\nlog dump\nping\ncd /gs\nping\nradio status\nstatu\n";
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            // sat/obc has a ping of its own, gs doesn't
+            assert_eq!(
+                accepted,
+                vec!["status", "status", "log dump", "ping", "ping", "radio status"]
+            );
+            assert_eq!(String::from_utf8(output).unwrap().matches("USAGE").count(), 1);
+        }
+
+        #[test]
+        fn local_before_global() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut tree = yaml::to_tree(&yaml[0]).unwrap();
+            let global = tree.insert_at_path(&[], Node::new("ping", "", 0));
+            tree.arena[global].get_mut().depth = Depth::Any;
+            let local = tree.find_by_path(&["sat", "obc", "ping"]).unwrap();
+
+            let mut cli = Cli::open(CliConfig::new("$: ", tree).unwrap());
+            let (node, _) = cli.change_root("cd sat/obc").unwrap();
+            cli.current_root = node;
+
+            let clicmds = Cli::construct_clicmds("ping", ' ').unwrap();
+            let (_, leaf) = cli.build_subtree(cli.current_root, &clicmds);
+            assert_eq!(cli.config.valid_cmds.resolve(leaf), local);
+
+            let clicmds = Cli::construct_clicmds("set ping", ' ').unwrap();
+            let (_, leaf) = cli.build_subtree(cli.current_root, &clicmds);
+            assert_eq!(cli.config.valid_cmds.resolve(leaf), global);
+        }
+    }
+
+    mod args {
+        use super::*;
+
+        const ARGS: &str =
+        "
+        gs:
+        - radio:
+          - set_freq <hz:int>:
+            'Set the frequency'
+          - mode <name>:
+            - now
+            - later
+          - ping
+        ";
+
+        fn run_args(script: &str) -> (String, Vec<String>) {
+            let yaml = YamlLoader::load_from_str(ARGS).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            (String::from_utf8(output).unwrap(), accepted)
+        }
+
+        #[test]
+        fn value() {
+            let (output, accepted) = run_args("gs radio set_freq 433\ngs radio mode safe later\n");
+
+            assert!(output.contains("$: ACCEPTED hz=433\n"));
+            assert!(output.contains("$: ACCEPTED name=safe\n"));
+            assert_eq!(accepted, vec!["gs radio set_freq 433", "gs radio mode safe later"]);
+        }
+
+        #[test]
+        fn command_handlers() {
+            let yaml = YamlLoader::load_from_str(ARGS).unwrap();
+            let calls: RefCell<Vec<String>> = RefCell::new(Vec::new());
+            let mut accepted = 0;
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_command("gs/radio/set_freq", |args| calls.borrow_mut().push(format!("set_freq {}", args.join(" "))))
+                .on_command("/gs/radio/mode/later", |args| calls.borrow_mut().push(format!("later {}", args.join(" "))))
+                .on_accept(|_, _| accepted += 1);
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            let script = "gs radio set_freq 433\ncd gs\nradio mode safe later\nradio ping\ngs radio set_freq\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+            let output = String::from_utf8(output).unwrap();
+
+            assert_eq!(calls.into_inner(), vec!["set_freq 433", "later safe"]);
+            assert_eq!(accepted, 3);
+            // Commands without a handler of their own are still reported
+            assert_eq!(output.matches("ACCEPTED").count(), 1);
+            assert!(output.contains("gs$: ACCEPTED\n"));
+        }
+
+        #[test]
+        fn missing_or_mistyped() {
+            let (output, accepted) = run_args("gs radio set_freq\ngs radio set_freq abc\ngs radio mode safe\n");
+
+            assert!(accepted.is_empty());
+            assert_eq!(output.matches("USAGE").count(), 3);
+            assert!(output.contains("\t* set_freq <hz:int>: Set the frequency\n\t* mode <name>\n\t* ping\n"));
+            assert!(output.contains("Usage: gs radio mode safe <cmd>\n"));
+        }
     }
 
-    mod cd {
+    mod suggest {
         use super::*;
 
         #[test]
-        fn should_change_root() {
-            assert_eq!(true, Cli::should_change_root("cd"));
-            assert_ne!(true, Cli::should_change_root("c"));
-            assert_ne!(true, Cli::should_change_root("d"));
-            assert_ne!(true, Cli::should_change_root(""));
-            assert_eq!(true, Cli::should_change_root("cd -"));
-            assert_eq!(true, Cli::should_change_root("cd ."));
-            assert_eq!(true, Cli::should_change_root("cd .."));
-            assert_eq!(true, Cli::should_change_root("cd sat"));
-            assert_eq!(true, Cli::should_change_root("cd /sat"));
-            assert_eq!(true, Cli::should_change_root("cd /.."));
-            assert_eq!(true, Cli::should_change_root("cd \t"));
-            assert_eq!(true, Cli::should_change_root("cd\n"));
+        fn typo() {
+            let output = run::run_script("sat obc pnig\nsat pya ping\n");
+
+            assert!(output.contains("\t* set\ndid you mean 'ping'?\n"));
+            assert!(output.contains("did you mean 'pay'?\n"));
         }
 
         #[test]
-        fn change_root_normal() {
-            let yaml = YamlLoader::load_from_str(&YAMLDOC).unwrap();
+        fn nothing_close() {
+            let output = run::run_script("sat obc reboot\nsat obc\n");
+
+            assert_eq!(output.matches("USAGE").count(), 2);
+            assert!(!output.contains("did you mean"));
+        }
+    }
+
+    mod delimiter {
+        use super::*;
+
+        #[test]
+        fn dots() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .delimiter('.')
+                .on_accept(|path, _| accepted.push(path.join(".")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            assert_eq!(cli.complete("sat.o"), (4, vec!["obc"]));
+            assert!(cli.complete("sat obc ").1.is_empty());
+
+            // cd keeps taking /-separated paths
+            cli.run_with(
+                Cursor::new("sat.obc.ping\nsat obc ping\ncd sat/obc\nping\ncd ..\nhelp pay.take_pic\nadcs\n".as_bytes()),
+                &mut output,
+            ).unwrap();
+            drop(cli);
+            let output = String::from_utf8(output).unwrap();
+
+            assert_eq!(accepted, vec!["sat.obc.ping", "ping"]);
+            assert!(output.contains("take_pic"));
+            assert!(output.contains("Usage: adcs.<cmd>\n"));
+        }
+
+        #[test]
+        fn clicmds() {
+            let clicmds = Cli::construct_clicmds("sat.obc.ping", '.').unwrap();
+            let words: Vec<&str> = clicmds.iter().map(|cmd| &*cmd.cmd).collect();
+            assert_eq!(words, vec!["sat", "obc", "ping"]);
+            assert_eq!(clicmds[2].depth, Depth::Some(3));
+
+            assert_eq!(Cli::construct_clicmds("sat.obc.ping", ' ').unwrap().len(), 1);
+        }
+
+        #[test]
+        fn path_separator() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .path_separator(':')
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            let script = "cd sat:obc\npwd\nping\ncd ..:..:gs\nls :sat\n:sat:pay:take_pic\ncd :gs:radio\ncd sat/obc\n";
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            assert_eq!(cli.current_prompt, "gs:radio");
+            drop(cli);
+            let output = String::from_utf8(output).unwrap();
+
+            assert_eq!(accepted, vec!["ping", "sat pay take_pic"]);
+            assert!(output.contains("sat:obc$: :sat:obc\n"));
+            assert!(output.contains("gs$: obc:  adcs:  pay:\n"));
+            assert!(output.contains("No such path: sat/obc"));
+        }
+    }
+
+    mod case {
+        use super::*;
+
+        fn run_case(case_insensitive: bool, script: &str) -> (String, Vec<String>) {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .case_insensitive(case_insensitive)
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            (String::from_utf8(output).unwrap(), accepted)
+        }
+
+        #[test]
+        fn sensitive_by_default() {
+            let (output, accepted) = run_case(false, "SAT OBC PING\ncd Sat\n");
+
+            assert!(accepted.is_empty());
+            assert!(output.contains("USAGE"));
+            assert!(output.contains("No such path: Sat\n"));
+        }
+
+        #[test]
+        fn insensitive() {
+            let (output, accepted) = run_case(true, "SAT OBC PING\ncd Sat\nObc Ping\n");
+
+            assert_eq!(accepted, vec!["sat obc ping", "obc ping"]);
+            assert!(output.contains("sat$: "));
+            assert!(!output.contains("USAGE"));
+        }
+    }
+
+    mod script {
+        use super::*;
+
+        const SCRIPT: &str = "# Check the satellite\n\ncd sat\nobc ping\n  # indented comment\nobc\npay take_pic\n";
+
+        fn run_lines(config: CliConfig, script: &str) -> (String, Result<(), String>) {
+            let mut cli = Cli::open(config);
+            let output = RefCell::new(Vec::new());
+
+            let result = cli
+                .run_lines(script, &mut Shared(&output), &mut Shared(&output))
+                .map_err(|e| e.to_string());
+            (String::from_utf8(output.into_inner()).unwrap(), result)
+        }
+
+        fn config(yaml: &yaml_rust::Yaml) -> CliConfig<'_> {
+            CliConfig::new("$: ", yaml::to_tree(yaml).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn runs_to_end() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let (output, result) = run_lines(config(&yaml[0]), SCRIPT);
+
+            assert!(output.starts_with("$: cd sat\nsat$: obc ping\nACCEPTED\nsat$: obc\nUSAGE\n"));
+            assert!(output.ends_with("sat$: pay take_pic\nACCEPTED\n"));
+            assert!(!output.contains('#'));
+            assert_eq!(result, Err("Line 6 was not accepted: obc".to_string()));
+        }
+
+        #[test]
+        fn stops_on_failure() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let (output, result) = run_lines(config(&yaml[0]).stop_on_failure(true), SCRIPT);
+
+            assert!(!output.contains("take_pic"));
+            assert_eq!(result, Err("Line 6 was not accepted: obc".to_string()));
+        }
+
+        #[test]
+        fn all_accepted() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let (output, result) = run_lines(config(&yaml[0]), "sat obc ping\nhelp\nexit\ncd nowhere\n");
+
+            assert_eq!(output.matches("ACCEPTED").count(), 1);
+            assert!(!output.contains("nowhere"));
+            assert!(result.is_ok());
+        }
+
+        #[test]
+        fn failed_builtins() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+
+            for line in ["cd nowhere", "ls nowhere", "help nowhere"] {
+                let (_, result) = run_lines(config(&yaml[0]), line);
+                assert_eq!(result, Err(format!("Line 1 was not accepted: {}", line)));
+            }
+        }
+
+        #[test]
+        fn file() {
+            let path = std::env::temp_dir().join(format!("gs-cli-script-{}", std::process::id()));
+            fs::write(&path, "sat obc ping\n").unwrap();
+
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = Cli::open(config(&yaml[0]));
+            let result = cli.run_script(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(result.is_ok());
+
+            assert!(matches!(cli.run_script(&path), Err(CliError::Io(_))));
+        }
+    }
+
+    mod source {
+        use super::*;
+
+        fn temp_path(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("gs-cli-{}-{}", name, std::process::id()))
+        }
+
+        #[test]
+        fn inline() {
+            let path = temp_path("source-inline");
+            fs::write(&path, "# setup\ncd /sat\nobc ping\n").unwrap();
+
+            let script = format!("cd gs\nsource {}\nradio ping\n", path.display());
+            let output = run::run_script(&script);
+            fs::remove_file(&path).unwrap();
+
+            // The sourced cd doesn't outlive the file
+            assert!(output.contains("sat$: obc ping\nACCEPTED\ngs$: ACCEPTED\n"));
+            assert_eq!(output.matches("ACCEPTED").count(), 2);
+        }
+
+        #[test]
+        fn recursion() {
+            let path = temp_path("source-self");
+            fs::write(&path, format!("sat obc ping\nsource {}\n", path.display())).unwrap();
+
+            let output = run::run_script(&format!("source {}\n", path.display()));
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(output.matches("ACCEPTED").count(), 1);
+            assert!(output.contains("already being sourced\n"));
+        }
+
+        #[test]
+        fn missing_file() {
+            let path = temp_path("source-missing");
+            let output = run::run_script(&format!("source {}\nsource\n", path.display()));
+
+            assert!(output.contains(&format!("source: {}: ", path.display())));
+            assert!(output.contains("Usage: source <file>\n"));
+        }
+    }
+
+    mod reload {
+        use super::*;
+
+        fn temp_path(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("gs-cli-{}-{}", name, std::process::id()))
+        }
+
+        fn load(path: &Path) -> Result<Tree<'static>, String> {
+            let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let docs = YamlLoader::load_from_str(&text).map_err(|e| e.to_string())?;
+            yaml::to_tree(&docs[0]).map(Tree::into_owned).map_err(|e| e.to_string())
+        }
+
+        // Nothing outlives the document but the cli built from it
+        fn owned_cli() -> Cli<'static> {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap().into_owned();
+            Cli::open(CliConfig::new("$: ", tree).unwrap())
+        }
+
+        #[test]
+        fn owned_tree() {
+            let mut cli = owned_cli();
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd sat/obc\nping\nset\n".as_bytes()), &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.matches("ACCEPTED").count(), 2);
+            assert_eq!(cli.current_prompt, "sat/obc");
+        }
+
+        #[test]
+        fn new_command() {
+            let path = temp_path("reload");
+            fs::write(&path, "sat:\n- ping\n").unwrap();
+
+            let config = CliConfig::new("$: ", load(&path).unwrap())
+                .unwrap()
+                .reload_with(|| load(&path));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("cd sat\nping\nreboot\n".as_bytes()), &mut output).unwrap();
+            fs::write(&path, "sat:\n- ping\n- reboot\n").unwrap();
+            cli.run_with(Cursor::new("reload\nsat reboot\n".as_bytes()), &mut output).unwrap();
+            fs::write(&path, "sat: [").unwrap();
+            cli.run_with(Cursor::new("reload\nsat reboot\n".as_bytes()), &mut output).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.matches("ACCEPTED").count(), 3);
+            assert_eq!(output.matches("USAGE").count(), 1);
+            assert!(output.contains("reload: "));
+            assert_eq!(cli.current_prompt, "");
+        }
+
+        #[test]
+        fn without_loader() {
+            let output = run::run_script("cd sat\nreload\npwd\n");
+            assert!(output.contains("reload: nothing to reload from\n"));
+            assert!(output.contains("/sat\n"));
+        }
+    }
+
+    mod tree {
+        use super::*;
+
+        #[test]
+        fn whole() {
+            let output = run::run_script("tree\n");
+
+            assert_eq!(output, "\
+$: /
+├── sat
+│   ├── obc
+│   │   ├── ping
+│   │   └── set
+│   ├── adcs
+│   │   ├── ping
+│   │   └── set
+│   └── pay
+│       ├── ping
+│       └── take_pic
+└── gs
+    ├── radio
+    │   ├── ping
+    │   └── set_freq
+    └── sys
+        └── config
+$: \n");
+        }
+
+        #[test]
+        fn current_root() {
+            let output = run::run_script("cd sat/pay\ntree\n");
+
+            assert!(output.contains("sat/pay$: /sat/pay\n├── ping\n└── take_pic\nsat/pay$: "));
+        }
+    }
+
+    mod aliases {
+        use super::*;
+
+        const ALIASES: &str =
+        "
+        gs:
+        - sys:
+          - config:
+              aliases: [cfg, conf]
+              children: [set, get]
+        ";
+
+        fn run_aliases(script: &str) -> (String, Vec<String>) {
+            let yaml = YamlLoader::load_from_str(ALIASES).unwrap();
+            let mut accepted: Vec<String> = Vec::new();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|path, _| accepted.push(path.join(" ")));
+            let mut cli = Cli::open(config);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            drop(cli);
+
+            (String::from_utf8(output).unwrap(), accepted)
+        }
+
+        #[test]
+        fn navigates() {
+            let (output, _) = run_aliases("cd gs/sys/cfg\npwd\n");
+
+            assert!(output.contains("gs/sys/config$: /gs/sys/config\n"));
+        }
+
+        #[test]
+        fn canonical_name() {
+            let (output, accepted) = run_aliases("gs sys cfg set\ngs sys conf get\ngs sys cfg\n");
+
+            assert_eq!(accepted, vec!["gs sys config set", "gs sys config get"]);
+            assert!(output.contains("Usage: gs sys config <cmd>\n"));
+        }
+    }
+
+    mod hidden {
+        use super::*;
+
+        const HIDDEN: &str =
+        "
+        sat:
+        - obc:
+          - ping
+          - dump_mem:
+              hidden: true
+          - reboot
+        ";
+
+        fn run_hidden(script: &str) -> String {
+            let yaml = YamlLoader::load_from_str(HIDDEN).unwrap();
+            let mut cli = get_cli(&yaml[0]);
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new(script.as_bytes()), &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn not_listed() {
+            let output = run_hidden("sat obc\nhelp sat obc\ncd sat/obc\nhelp\nls\ntree\n");
+
+            assert!(output.contains("Usage: sat obc <cmd>\nWhere 'cmd' can be either of\n\t* ping\n\t* reboot\n"));
+            assert!(output.contains("ping  reboot\n"));
+            assert!(output.contains("├── ping\n└── reboot\n"));
+            assert!(!output.contains("dump_mem"));
+        }
+
+        #[test]
+        fn still_accepted() {
+            let output = run_hidden("sat obc dump_mem\nsat obc dump_me\n");
+
+            assert!(output.contains("$: ACCEPTED\n"));
+            assert!(!output.contains("did you mean"));
+        }
+
+        #[test]
+        fn not_completed() {
+            let yaml = YamlLoader::load_from_str(HIDDEN).unwrap();
             let cli = get_cli(&yaml[0]);
-            let arena = &cli.config.valid_cmds.arena;
 
-            let (node, prompt) = cli.change_root("cd sat");
-            assert_eq!(prompt, "sat");
+            assert!(cli.complete("sat obc d").1.is_empty());
+        }
+    }
+
+    mod validate {
+        use super::*;
+
+        fn usage_of<'c>(result: Result<Vec<Node<'c>>, InputError<'c>>) -> UsageError<'c> {
+            match result {
+                Err(InputError::Usage(usage)) => usage,
+                other => panic!("expected a usage error, got {:?}", other),
+            }
+        }
+
+        #[test]
+        fn accepted() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            let matched = cli.handle_input("sat obc ping").unwrap();
+            let names: Vec<&str> = matched.iter().map(|node| &*node.name).collect();
+            assert_eq!(names, vec!["sat", "obc", "ping"]);
+        }
+
+        #[test]
+        fn repeated_spaces() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            assert_eq!(cli.handle_input("sat  obc  ping").unwrap(), cli.handle_input("sat obc ping").unwrap());
             assert_eq!(
-                Node::from_id(&node, &arena),
-                Node::new("sat", "", Depth::Some(1))
+                Cli::construct_clicmds("sat  obc  ping", ' ').unwrap(),
+                Cli::construct_clicmds("sat obc ping", ' ').unwrap()
+            );
+            assert_eq!(
+                usage_of(cli.handle_input("sat  obc")).expected,
+                usage_of(cli.handle_input("sat obc")).expected
             );
+        }
+
+        #[test]
+        fn usage() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            let usage = usage_of(cli.handle_input("sat obc pnig"));
+            assert_eq!(usage.matched.iter().map(|node| &*node.name).collect::<Vec<_>>(), vec!["sat", "obc"]);
+            assert_eq!(usage.expected.iter().map(|node| &*node.name).collect::<Vec<_>>(), vec!["ping", "set"]);
+            assert_eq!(usage.last_valid_node, cli.config.valid_cmds.find_by_path(&["sat", "obc"]).unwrap());
+            assert_eq!(usage.unmatched.as_deref(), Some("pnig"));
+
+            let usage = usage_of(cli.handle_input("sat"));
+            assert_eq!(usage.unmatched, None);
+            assert_eq!(usage.expected.len(), 3);
+        }
+
+        #[test]
+        fn foreign_usage() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+            let small = YamlLoader::load_from_str("sat:\n- ping").unwrap();
+            let other = get_cli(&small[0]);
+
+            // The node this usage points at doesn't exist in the other tree
+            let usage = usage_of(cli.handle_input("gs radio"));
+            let mut output = Vec::new();
+            other.print_usage(&usage, &mut output).unwrap();
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "Incomplete command 'gs radio'\nUsage: gs radio <cmd>\nWhere 'cmd' can be either of\n"
+            );
+        }
+
+        #[test]
+        fn results() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+            let obc = cli.config.valid_cmds.find_by_path(&["sat", "obc"]).unwrap();
+
+            match cli.validate("sat obc ping") {
+                ValidationResult::Accepted(matched) => assert_eq!(matched.len(), 3),
+                other => panic!("expected sat obc ping to be accepted, got {:?}", other),
+            }
+            match cli.validate("sat obc") {
+                ValidationResult::Incomplete { last_valid, options } => {
+                    assert_eq!(last_valid, obc);
+                    assert_eq!(options.iter().map(|node| &*node.name).collect::<Vec<_>>(), vec!["ping", "set"]);
+                }
+                other => panic!("expected sat obc to be incomplete, got {:?}", other),
+            }
+            assert!(matches!(cli.validate(""), ValidationResult::Incomplete { options, .. } if options.len() == 2));
+
+            for line in ["sat obc pnig", "foo", "sat obc ping extra", "sat \"obc"] {
+                assert_eq!(cli.validate(line), ValidationResult::Unknown, "{}", line);
+            }
+        }
+
+        #[test]
+        fn prints_nothing() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut accepted = 0;
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .on_accept(|_, _| accepted += 1);
+            let cli = Cli::open(config);
+
+            assert!(cli.handle_input("sat obc ping").is_ok());
+            drop(cli);
+            assert_eq!(accepted, 0);
+        }
+    }
+
+    mod grammar {
+        use super::*;
+
+        #[test]
+        fn listing() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let cli = get_cli(&yaml[0]);
+
+            assert_eq!(
+                cli.export_grammar(),
+                "/sat/obc/ping\t\n/sat/obc/set\t\n/sat/adcs/ping\t\n/sat/adcs/set\t\n\
+                 /sat/pay/ping\t\n/sat/pay/take_pic\t\n\
+                 /gs/radio/ping\t\n/gs/radio/set_freq\t\n/gs/sys/config\t\n"
+            );
+        }
+
+        #[test]
+        fn json() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n- obc:\n  - ping: 'Check the obc is alive'\n  - dump:\n      hidden: true\n- pay: 'The payload'"
+            ).unwrap();
+            let config = CliConfig::new("$: ", yaml::to_tree(&yaml[0]).unwrap())
+                .unwrap()
+                .output_mode(OutputMode::Json);
+            let cli = Cli::open(config);
+
+            assert_eq!(
+                cli.export_grammar(),
+                r#"[{"path":["sat","obc","ping"],"explanation":"Check the obc is alive"},{"path":["sat","pay"],"explanation":"The payload"}]"#
+            );
+        }
+    }
+
+    mod records {
+        use super::*;
+
+        fn config(yaml: &yaml_rust::Yaml) -> CliConfig<'_> {
+            CliConfig::new("$: ", yaml::to_tree(yaml).unwrap())
+                .unwrap()
+                .output_mode(OutputMode::Json)
+        }
+
+        #[test]
+        fn inline() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut cli = Cli::open(config(&yaml[0]));
+            let mut output = Vec::new();
+
+            cli.run_with(Cursor::new("sat obc ping\ncd gs\nradio set_freq\nradio\n".as_bytes()), &mut output).unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with("$: {\"path\":[\"sat\",\"obc\",\"ping\"],\"status\":\"accepted\"}\n"));
+            assert!(output.contains("gs$: {\"path\":[\"radio\",\"set_freq\"],\"status\":\"accepted\"}\n"));
+            assert!(output.contains("USAGE"));
+            assert!(!output.contains("ACCEPTED"));
+        }
+
+        #[test]
+        fn sink() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut records = Vec::new();
+            let mut output = Vec::new();
+
+            let mut cli = Cli::open(config(&yaml[0]).record_sink(&mut records));
+            cli.run_with(Cursor::new("sat pay take_pic\nsat\n".as_bytes()), &mut output).unwrap();
+            drop(cli);
 
-            let (node, prompt) = cli.change_root("cd -");
-            assert_eq!(prompt, "");
             assert_eq!(
-                Node::from_id(&node, &arena),
-                Node::new("root", "", Depth::Some(0))
+                String::from_utf8(records).unwrap(),
+                "{\"path\":[\"sat\",\"pay\",\"take_pic\"],\"status\":\"accepted\"}\n"
             );
+            assert!(!String::from_utf8(output).unwrap().contains("accepted"));
         }
     }
-}
\ No newline at end of file
+}
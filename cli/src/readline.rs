@@ -0,0 +1,201 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+use rustyline::completion::Completer;
+use rustyline::config::Config;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use translator::{NodeId, Tree};
+
+use crate::{Cli, CliError};
+
+/// Tab completion for rustyline, as in the built-in editor. Commands run
+/// between lines need the CLI mutably, so rather than borrowing it this keeps
+/// a copy of the tree, replaced after a reload, and of the current root.
+struct Completions<'a> {
+    tree: Tree<'a>,
+    root: NodeId,
+    delimiter: char,
+    separator: char,
+    case_insensitive: bool,
+    /// Whether the line being edited is empty, which rustyline doesn't tell
+    /// on Ctrl-C. Kept up to date by `hint`, which sees every edit.
+    empty: Cell<bool>,
+}
+
+impl Completer for Completions<'_> {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        // Completion works on the last word, so only at the end of the line
+        if pos < line.len() {
+            return Ok((pos, Vec::new()));
+        }
+
+        let (start, candidates) =
+            crate::completions(&self.tree, self.root, self.delimiter, self.separator, self.case_insensitive, line);
+        Ok((start, candidates.into_iter().map(String::from).collect()))
+    }
+}
+
+impl Hinter for Completions<'_> {
+    type Hint = String;
+
+    fn hint(&self, line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        self.empty.set(line.is_empty());
+        None
+    }
+}
+
+impl Highlighter for Completions<'_> {}
+
+impl Validator for Completions<'_> {}
+
+impl Helper for Completions<'_> {}
+
+impl<'a> Cli<'a> {
+    /// The session loop of `run_with_streams`, reading lines through
+    /// rustyline on the terminal. History is shared both ways: rustyline
+    /// starts with the CLI's history and every line goes to both.
+    pub(crate) fn run_readline(&mut self) -> Result<(), CliError<'a>> {
+        let config = Config::builder()
+            .max_history_size(self.config.history_size)
+            .and_then(|builder| builder.history_ignore_dups(true))
+            .map_err(io_error)?
+            .build();
+        let mut editor: Editor<Completions, DefaultHistory> = Editor::with_config(config).map_err(io_error)?;
+        for line in &self.history {
+            editor.add_history_entry(line.as_str()).map_err(io_error)?;
+        }
+
+        let stdin = io::stdin();
+        let mut out = io::stdout();
+        let mut err = io::stderr();
+
+        if let Some(notice) = self.notice.take() {
+            writeln!(err, "{}", notice)?;
+        }
+
+        editor.set_helper(Some(Completions {
+            tree: self.config.valid_cmds.clone(),
+            root: self.current_root,
+            delimiter: self.config.delimiter,
+            separator: self.config.path_separator,
+            case_insensitive: self.config.case_insensitive,
+            empty: Cell::new(true),
+        }));
+
+        loop {
+            out.flush()?;
+            let mut prompt = Vec::new();
+            self.print_prompt(&mut prompt)?;
+            if let Some(helper) = editor.helper_mut() {
+                helper.root = self.current_root;
+                helper.empty.set(true);
+            }
+
+            let input = match editor.readline(&String::from_utf8_lossy(&prompt)) {
+                Ok(input) => input,
+                // As in the built-in editor, Ctrl-C only ends the session on an empty line
+                Err(ReadlineError::Interrupted)
+                    if self.config.catch_interrupt && !editor.helper().is_some_and(|helper| helper.empty.get()) =>
+                {
+                    continue
+                }
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => {
+                    writeln!(err, "logout")?;
+                    break;
+                }
+                Err(e) => {
+                    // Keep what was entered so far even if the session dies
+                    self.save_history()?;
+                    return Err(io_error(e).into());
+                }
+            };
+
+            let line = Cli::normalize(&input);
+            self.add_history(line);
+            editor.add_history_entry(line).map_err(io_error)?;
+
+            if self.should_exit(line) {
+                break;
+            } else if Cli::should_new_prompt(line) {
+                continue;
+            }
+
+            self.dispatch(Cli::with_cd_whitespace(&input, line), &mut stdin.lock(), &mut out, &mut err)?;
+
+            // A sourced file may reload as well
+            if Cli::should_reload(line) || Cli::should_source(line) {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.tree = self.config.valid_cmds.clone();
+                }
+            }
+        }
+
+        self.save_history()?;
+        self.save_state()?;
+        out.flush()?;
+        err.flush()?;
+
+        Ok(())
+    }
+}
+
+fn io_error(e: ReadlineError) -> io::Error {
+    match e {
+        ReadlineError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn completes_last_word() {
+        let yaml = YamlLoader::load_from_str("sat:\n- obc:\n  - ping\n  - power\n  - dump:\n      hidden: true\n- pay").unwrap();
+        let tree = translator::yaml::to_tree(&yaml[0]).unwrap();
+        let completions = Completions {
+            root: tree.root,
+            tree,
+            delimiter: ' ',
+            separator: '/',
+            case_insensitive: false,
+            empty: Cell::new(true),
+        };
+        let history = DefaultHistory::new();
+        let complete = |line: &str, pos: usize| completions.complete(line, pos, &Context::new(&history)).unwrap();
+
+        assert_eq!(complete("sat obc p", 9), (8, vec!["ping".to_string(), "power".to_string()]));
+        assert_eq!(complete("sat obc d", 9), (8, vec![]));
+        assert_eq!(complete("s", 1), (0, vec!["sat".to_string()]));
+        assert_eq!(complete("sat pa", 3), (3, vec![]));
+    }
+
+    #[test]
+    fn tracks_empty_line() {
+        let yaml = YamlLoader::load_from_str("sat:\n- obc").unwrap();
+        let tree = translator::yaml::to_tree(&yaml[0]).unwrap();
+        let completions = Completions {
+            root: tree.root,
+            tree,
+            delimiter: ' ',
+            separator: '/',
+            case_insensitive: false,
+            empty: Cell::new(true),
+        };
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+
+        assert_eq!(completions.hint("sat", 3, &ctx), None);
+        assert!(!completions.empty.get());
+        completions.hint("", 0, &ctx);
+        assert!(completions.empty.get());
+    }
+}
@@ -0,0 +1,66 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use translator::yaml;
+use yaml_rust::YamlLoader;
+
+/// Counts allocations, to show what owning the names costs besides time.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// A document with `groups` groups of `groups` subgroups, each with ten
+/// explained commands, one of them taking an argument.
+fn document(groups: usize) -> String {
+    let mut doc = String::new();
+    for g in 0..groups {
+        doc.push_str(&format!("group{}:\n", g));
+        for s in 0..groups {
+            doc.push_str(&format!("- sub{}:\n", s));
+            for c in 0..9 {
+                doc.push_str(&format!("  - cmd{}: 'Run command {} of sub{}'\n", c, c, s));
+            }
+            doc.push_str("  - set <value:int>: 'Set a value'\n");
+        }
+    }
+    doc
+}
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn construction(c: &mut Criterion) {
+    let yaml = YamlLoader::load_from_str(&document(10)).unwrap();
+    let doc = &yaml[0];
+
+    let nodes = yaml::to_tree(doc).unwrap().arena.len();
+    let borrowed = allocations(|| drop(yaml::to_tree(doc).unwrap()));
+    let owned = allocations(|| drop(yaml::to_tree(doc).unwrap().into_owned()));
+    println!("{} nodes: {} allocations borrowed, {} owned", nodes, borrowed, owned);
+
+    let mut group = c.benchmark_group("construction");
+    group.bench_function("borrowed", |b| b.iter(|| yaml::to_tree(doc).unwrap()));
+    group.bench_function("owned", |b| b.iter(|| yaml::to_tree(doc).unwrap().into_owned()));
+    group.finish();
+}
+
+criterion_group!(benches, construction);
+criterion_main!(benches);
@@ -1,42 +1,505 @@
 pub use indextree::NodeId;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
-pub type NodeArena<'a> = indextree::Arena<Node<'a>>;
+pub type NodeArena<'a, T = ()> = indextree::Arena<Node<'a, T>>;
 
-pub struct Tree<'a> {
+/// A command tree. Every node carries a `T`, see `Node::payload`.
+pub struct Tree<'a, T = ()> {
     pub root: NodeId,
-    pub arena: NodeArena<'a>,
+    pub arena: NodeArena<'a, T>,
+    counts: RefCell<SubtreeCounts>,
+    /// Tells this tree's `TreeRef`s apart from other trees'.
+    id: usize,
 }
 
-impl<'a> Tree<'a> {
-    pub fn new() -> Tree<'a> {
-        Tree::new_depth(Depth::Some(0))
+/// The name the root node is given. It's only ever shown, never matched:
+/// the root is told apart by its id, so a command may be named the same.
+pub const ROOT_NAME: &str = "root";
+
+static NEXT_TREE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn next_tree_id() -> usize {
+    NEXT_TREE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A `NodeId` that remembers which tree it belongs to, for ids handed from
+/// one tree's code to another's. A plain `NodeId` looked up in the wrong
+/// arena silently finds whatever node sits there, while `Tree::resolve`
+/// panics on a `TreeRef` from another tree.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TreeRef {
+    tree: usize,
+    node: NodeId,
+}
+
+/// Subtree sizes of every node below the root, valid while the arena still
+/// has the same number of slots and live nodes as recorded in `arena_size`.
+#[derive(Default)]
+struct SubtreeCounts {
+    arena_size: (usize, usize),
+    counts: HashMap<NodeId, usize>,
+}
+
+impl<'a, T: Default> Tree<'a, T> {
+    pub fn new() -> Tree<'a, T> {
+        Tree::new_depth(0)
+    }
+
+    /// A tree whose root sits at `depth` rather than 0, for grafting below an
+    /// existing hierarchy. Depths are only ever compared relative to a node's
+    /// parent, here by `insert_at_path` and by the cli when matching input, so
+    /// any root depth works. A root at `Depth::Any` makes everything
+    /// inserted below it `Depth::Any` as well. The loaders in `yaml`, `json`
+    /// and so on always start from `new`.
+    pub fn new_depth(depth: impl Into<Depth>) -> Tree<'a, T> {
+        let mut arena = NodeArena::new();
+        let root = arena.new_node(Node::new(ROOT_NAME, "", depth).with_payload(T::default()));
+        Tree { root, arena, counts: RefCell::default(), id: next_tree_id() }
+    }
+
+    /// Appends `node` below the node at `path`, creating any missing nodes on
+    /// the way without explanations. Every node gets its parent's depth plus
+    /// one, whatever depth `node` came with.
+    pub fn insert_at_path(&mut self, path: &[&'a str], node: Node<'a, T>) -> NodeId {
+        let mut parent = self.root;
+
+        for segment in path {
+            let existing = parent
+                .children(&self.arena)
+                .find(|child| self.arena[*child].get().name == *segment);
+
+            parent = match existing {
+                Some(child) => child,
+                None => self.append_child(parent, Node::new(*segment, "", Depth::Any).with_payload(T::default())),
+            };
+        }
+
+        self.append_child(parent, node)
+    }
+}
+
+impl<'a, T> Tree<'a, T> {
+    /// Tags `node` as one of this tree's nodes.
+    pub fn node_ref(&self, node: NodeId) -> TreeRef {
+        TreeRef { tree: self.id, node }
+    }
+
+    /// Whether `node` was tagged by this tree.
+    pub fn owns(&self, node: TreeRef) -> bool {
+        node.tree == self.id
+    }
+
+    /// The id behind `node`.
+    ///
+    /// # Panics
+    ///
+    /// If `node` was tagged by another tree.
+    pub fn resolve(&self, node: TreeRef) -> NodeId {
+        assert!(self.owns(node), "node {:?} belongs to another tree", node.node);
+        node.node
+    }
+
+    /// Number of nodes below `node`, like the free `subtree_count` but cached.
+    ///
+    /// The cache is rebuilt whenever nodes are added to the arena. Moving or
+    /// removing nodes through `arena` directly may not be noticed, so call
+    /// `invalidate_counts` after doing that.
+    pub fn subtree_count(&self, node: &NodeId) -> usize {
+        let mut cache = self.counts.borrow_mut();
+        if cache.arena_size != arena_size(&self.arena) {
+            *cache = self.count_subtrees();
+        }
+
+        match cache.counts.get(node) {
+            Some(count) => *count,
+            // Not reachable from the root, so it was never cached
+            None => subtree_count(node, &self.arena),
+        }
+    }
+
+    /// Number of nodes directly below `node`, where `subtree_count` counts
+    /// every node below it.
+    pub fn child_count(&self, node: NodeId) -> usize {
+        node.children(&self.arena).count()
+    }
+
+    /// Whether nothing is below `node`, making it a command that can be run
+    /// rather than a group of commands.
+    pub fn is_leaf(&self, node: NodeId) -> bool {
+        node.children(&self.arena).next().is_none()
+    }
+
+    pub fn invalidate_counts(&mut self) {
+        *self.counts.get_mut() = SubtreeCounts::default();
+    }
+
+    fn count_subtrees(&self) -> SubtreeCounts {
+        let mut counts = HashMap::new();
+
+        // Reversed pre-order visits every child before its parent
+        let nodes: Vec<NodeId> = self.root.descendants(&self.arena).collect();
+        for node in nodes.into_iter().rev() {
+            let count = node
+                .children(&self.arena)
+                .map(|child| counts[&child] + 1)
+                .sum();
+            counts.insert(node, count);
+        }
+
+        SubtreeCounts { arena_size: arena_size(&self.arena), counts }
+    }
+
+    /// Removes `node` and everything below it. The root can't be removed;
+    /// passing it removes everything below the root instead.
+    ///
+    /// Ids of removed nodes are stale afterwards, including any a `Cli` keeps
+    /// as its current root, so callers must move those elsewhere first.
+    pub fn remove(&mut self, node: NodeId) {
+        if node == self.root {
+            let children: Vec<NodeId> = node.children(&self.arena).collect();
+            for child in children {
+                child.remove_subtree(&mut self.arena);
+            }
+        } else {
+            node.remove_subtree(&mut self.arena);
+        }
+
+        self.invalidate_counts();
+    }
+
+    /// Walks down from the root, matching one segment per level against node
+    /// names and aliases. An empty path resolves to the root itself.
+    pub fn find_by_path(&self, segments: &[&str]) -> Option<NodeId> {
+        self.find_by_path_from(self.root, segments)
+    }
+
+    /// Like `find_by_path`, but walks down from `start` instead of the root,
+    /// so an empty path resolves to `start`.
+    pub fn find_by_path_from(&self, start: NodeId, segments: &[&str]) -> Option<NodeId> {
+        self.find_by_path_with(start, segments, |name, segment| name == segment)
+    }
+
+    /// Like `find_by_path`, but ASCII letters match regardless of case.
+    pub fn find_by_path_ignore_case(&self, segments: &[&str]) -> Option<NodeId> {
+        self.find_by_path_with(self.root, segments, str::eq_ignore_ascii_case)
+    }
+
+    fn find_by_path_with(&self, start: NodeId, segments: &[&str], eq: impl Fn(&str, &str) -> bool) -> Option<NodeId> {
+        let mut node = start;
+
+        for segment in segments {
+            node = node
+                .children(&self.arena)
+                .find(|child| self.arena[*child].get().names().any(|name| eq(name, segment)))?;
+        }
+
+        Some(node)
+    }
+
+    /// Checks that no two siblings share a name or alias, since input could
+    /// only ever reach the first of them. The loaders in `yaml` do this for
+    /// you; trees from `json`, `toml` or built by hand can be checked here.
+    pub fn validate(&self) -> std::result::Result<(), TranslatorError> {
+        let mut path = Vec::new();
+        self.validate_rec(self.root, &mut path)
+    }
+
+    fn validate_rec<'s>(&'s self, node: NodeId, path: &mut Vec<&'s str>) -> std::result::Result<(), TranslatorError> {
+        let mut seen = HashSet::new();
+
+        for child in node.children(&self.arena) {
+            let child_node = self.arena[child].get();
+            if let Some(name) = child_node.names().find(|name| !seen.insert(*name)) {
+                return Err(TranslatorError::DuplicateCommand {
+                    path: path.join("/"),
+                    name: name.to_string(),
+                });
+            }
+
+            path.push(&child_node.name);
+            self.validate_rec(child, path)?;
+            path.pop();
+        }
+
+        Ok(())
+    }
+
+    fn append_child(&mut self, parent: NodeId, node: Node<'a, T>) -> NodeId {
+        let depth = match self.arena[parent].get().depth {
+            Depth::Some(d) => Depth::Some(d + 1),
+            Depth::Any => Depth::Any,
+        };
+
+        let child = Node::from_node_to_id(Node { depth, ..node }, &mut self.arena);
+        parent.append(child, &mut self.arena);
+        child
+    }
+
+    /// Every node below the root that has no children, in pre-order. These
+    /// are the commands that can be run, as opposed to groups of commands.
+    pub fn leaves(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.root
+            .descendants(&self.arena)
+            .skip(1)
+            .filter(|node| self.is_leaf(*node))
+    }
+
+    /// Names from below the root down to `node`, e.g. `["sat", "obc", "ping"]`.
+    /// Empty for the root itself.
+    pub fn path_of(&self, node: NodeId) -> Vec<&str> {
+        let mut path: Vec<&str> = node
+            .ancestors(&self.arena)
+            .take_while(|ancestor| *ancestor != self.root)
+            .filter_map(|ancestor| self.arena.get(ancestor).and_then(|n| n.try_get()))
+            .map(|ancestor| &*ancestor.name)
+            .collect();
+
+        path.reverse();
+        path
+    }
+
+    /// `node` and then each of its ancestors up to and including the root,
+    /// like `NodeId::ancestors` but with the nodes rather than their ids.
+    pub fn ancestors(&self, node: NodeId) -> impl Iterator<Item = Node<'a, T>> + '_
+    where
+        T: Clone,
+    {
+        node.ancestors(&self.arena).map(|ancestor| Node::from_id(&ancestor, &self.arena))
+    }
+
+    /// `node` and everything below it in pre-order, like
+    /// `NodeId::descendants` but with the nodes rather than their ids.
+    pub fn descendants(&self, node: NodeId) -> impl Iterator<Item = Node<'a, T>> + '_
+    where
+        T: Clone,
+    {
+        node.descendants(&self.arena).map(|descendant| Node::from_id(&descendant, &self.arena))
+    }
+
+    /// The whole tree as nested nodes, from the root down.
+    pub fn snapshot(&self) -> Snapshot<'a, T>
+    where
+        T: Clone,
+    {
+        self.snapshot_of(self.root)
+    }
+
+    fn snapshot_of(&self, node: NodeId) -> Snapshot<'a, T>
+    where
+        T: Clone,
+    {
+        Snapshot {
+            node: Node::from_id(&node, &self.arena),
+            children: node.children(&self.arena).map(|child| self.snapshot_of(child)).collect(),
+        }
+    }
+
+    pub fn iter(&self) -> TreeIter<'_, 'a, T>
+    where
+        T: Clone,
+    {
+        self.into_iter()
+    }
+
+    /// A copy of this tree with each payload replaced by what `payload`
+    /// returns for its node. `payload` is also given the names leading to the
+    /// node from the root, e.g. `["sat", "obc", "ping"]`, or none for the root.
+    pub fn map_payloads<U>(&self, mut payload: impl FnMut(&[&str], &Node<'a, T>) -> U) -> Tree<'a, U> {
+        self.map_nodes(|path, node| node.cloned_with(payload(path, node)))
     }
 
-    fn new_depth(depth: Depth) -> Tree<'a> {
+    /// A copy of this tree that owns all of its names and explanations, so
+    /// that it no longer borrows from the document it was loaded from. Node
+    /// ids may differ from this tree's, as with `map_payloads`.
+    pub fn into_owned(self) -> Tree<'static, T>
+    where
+        T: Clone,
+    {
+        self.map_nodes(|_, node| node.clone().into_owned())
+    }
+
+    fn map_nodes<'b, U>(&self, mut map: impl FnMut(&[&str], &Node<'a, T>) -> Node<'b, U>) -> Tree<'b, U> {
         let mut arena = NodeArena::new();
-        let root = arena.new_node(Node::new("root", "", depth));
-        Tree { root, arena }
+        let root = self.map_rec(self.root, &mut Vec::new(), &mut map, &mut arena);
+        Tree { root, arena, counts: RefCell::default(), id: next_tree_id() }
+    }
+
+    fn map_rec<'s, 'b, U>(
+        &'s self,
+        nid: NodeId,
+        path: &mut Vec<&'s str>,
+        map: &mut impl FnMut(&[&str], &Node<'a, T>) -> Node<'b, U>,
+        arena: &mut NodeArena<'b, U>,
+    ) -> NodeId {
+        let new_id = arena.new_node(map(path, self.arena[nid].get()));
+
+        for child in nid.children(&self.arena) {
+            path.push(&self.arena[child].get().name);
+            let new_child = self.map_rec(child, path, map, arena);
+            path.pop();
+            new_id.append(new_child, arena);
+        }
+
+        new_id
+    }
+
+    /// Renders the tree like the Unix `tree` utility, one node per line under
+    /// a `.` for the root.
+    pub fn draw(&self) -> String {
+        format!(".\n{}", self.draw_with(self.root, |node, _| node.name.to_string()))
+    }
+
+    /// Renders what's below `from` with `├──`/`└──`/`│` connectors, without a
+    /// line for `from` itself. Hidden nodes are left out along with their
+    /// subtrees. `label` is given each node and whether it has
+    /// children, and returns the text to show for it.
+    pub fn draw_with(&self, from: NodeId, label: impl Fn(&Node<'a, T>, bool) -> String) -> String {
+        let mut out = String::new();
+        self.draw_rec(from, "", &label, &mut out);
+        out
+    }
+
+    fn draw_rec(&self, node: NodeId, prefix: &str, label: &impl Fn(&Node<'a, T>, bool) -> String, out: &mut String) {
+        let mut children = node
+            .children(&self.arena)
+            .filter(|child| !self.arena[*child].get().hidden)
+            .peekable();
+
+        while let Some(child) = children.next() {
+            let last = children.peek().is_none();
+            let has_children = !self.is_leaf(child);
+
+            out.push_str(prefix);
+            out.push_str(if last { "└── " } else { "├── " });
+            out.push_str(&label(self.arena[child].get(), has_children));
+            out.push('\n');
+
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            self.draw_rec(child, &prefix, label, out);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TranslatorError {
+    /// A value at `path` had a different type than the schema allows there.
+    /// An empty path means the top-level document.
+    UnexpectedType {
+        path: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A command key declared its argument in a way that could not be
+    /// parsed, e.g. `set_freq <hz:integer>`.
+    InvalidArgument {
+        path: String,
+        key: String,
+    },
+    /// No document is named, or numbered, as the requested profile.
+    NoSuchProfile(String),
+    /// Two commands below `path` share `name`, as a name or an alias.
+    DuplicateCommand {
+        path: String,
+        name: String,
+    },
+    /// Commands at `path` are nested more than `max` levels below the root.
+    TooDeep {
+        path: String,
+        max: usize,
+    },
+}
+
+impl std::fmt::Display for TranslatorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            TranslatorError::UnexpectedType { path, expected, found } => {
+                let location = if path.is_empty() { "top level" } else { path };
+                write!(f, "Expected {} at {}, found {}", expected, location, found)
+            }
+            TranslatorError::InvalidArgument { path, key } => {
+                write!(f, "Invalid argument declaration '{}' at {}", key, path)
+            }
+            TranslatorError::NoSuchProfile(profile) => write!(f, "No such profile: {}", profile),
+            TranslatorError::DuplicateCommand { path, name } => {
+                let location = if path.is_empty() { "top level" } else { path };
+                write!(f, "Duplicate command '{}' at {}", name, location)
+            }
+            TranslatorError::TooDeep { path, max } => {
+                write!(f, "Commands nested deeper than {} levels at {}", max, path)
+            }
+        }
     }
 }
 
-pub fn subtree_count(node: &NodeId, arena: &NodeArena) -> usize {
+impl std::error::Error for TranslatorError {}
+
+// Adding a node grows one of these, even when it reuses a removed node's slot
+fn arena_size<T>(arena: &NodeArena<T>) -> (usize, usize) {
+    (arena.len(), arena.live_count())
+}
+
+pub fn subtree_count<T>(node: &NodeId, arena: &NodeArena<T>) -> usize {
     node.descendants(arena).into_iter().count() - 1
 }
 
-impl<'a> Debug for Tree<'a> {
+/// How many levels of commands the loaders build before giving up, unless
+/// told otherwise, so that a runaway document fails cleanly rather than
+/// overflowing the stack.
+pub const MAX_DEPTH: usize = 64;
+
+/// Largest edit distance at which `closest` still considers a candidate.
+pub const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// The candidate with the smallest Levenshtein distance to `input`, as long as
+/// it is within `MAX_SUGGESTION_DISTANCE`. Ties go to the shorter candidate.
+pub fn closest<'a>(input: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|candidate| (levenshtein(input, candidate), candidate.chars().count(), *candidate))
+        .filter(|(distance, _, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, len, _)| (*distance, *len))
+        .map(|(_, _, candidate)| candidate)
+}
+
+// A `timeout` value as a duration, unless it's negative or not finite
+fn seconds(seconds: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+impl<'a, T> Debug for Tree<'a, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         let root = self.root;
 
         for node in root.descendants(&self.arena) {
-            let data = Node::from_id(&node, &self.arena);
+            let data = self.arena[node].get();
             
             if let Depth::Some(d) = data.depth {
                 write!(f, "{}>", "\t".repeat(d))?;
             }
             write!(f, "{}", data.name)?;
             
-            if let Some(exp) = data.explanation {
+            if let Some(exp) = &data.explanation {
                 write!(f, ": {}", exp)?;
             }
             write!(f, "\n")?;
@@ -45,28 +508,74 @@ impl<'a> Debug for Tree<'a> {
     }
 }
 
-/* struct TreeIntoIterator {
-    tree: Tree,
-    index: usize,
+/// Depth-first, pre-order iterator over the nodes of a tree, root included.
+pub struct TreeIter<'t, 'a, T = ()> {
+    arena: &'t NodeArena<'a, T>,
+    descendants: indextree::Descendants<'t, Node<'a, T>>,
 }
 
-impl<'a> Iterator for TreeIntoIterator<'a> {
-    type Item = Node<'a>;
+impl<'t, 'a, T: Clone> Iterator for TreeIter<'t, 'a, T> {
+    type Item = Node<'a, T>;
+
     fn next(&mut self) -> Option<Self::Item> {
-        
+        self.descendants.next().map(|nid| Node::from_id(&nid, self.arena))
+    }
+}
+
+impl<'t, 'a, T: Clone> IntoIterator for &'t Tree<'a, T> {
+    type Item = Node<'a, T>;
+    type IntoIter = TreeIter<'t, 'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        TreeIter {
+            arena: &self.arena,
+            descendants: self.root.descendants(&self.arena),
+        }
     }
 }
 
-impl<'a> IntoIterator for NodeArena<'a> {
-    type Item = NodeId;
-    type IntoIter = TreeIntoIterator;
+/// A tree as nested nodes rather than an arena, for comparing trees or,
+/// with the `serde` feature, saving them. See `Tree::snapshot`; a tree is
+/// built back from one with `Tree::from`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot<'a, T = ()> {
+    pub node: Node<'a, T>,
+    pub children: Vec<Snapshot<'a, T>>,
+}
+
+impl<'a, T> From<Snapshot<'a, T>> for Tree<'a, T> {
+    fn from(snapshot: Snapshot<'a, T>) -> Tree<'a, T> {
+        fn append<'a, T>(parent: NodeId, children: Vec<Snapshot<'a, T>>, arena: &mut NodeArena<'a, T>) {
+            for child in children {
+                let node = parent.append_value(child.node, arena);
+                append(node, child.children, arena);
+            }
+        }
+
+        let mut arena = NodeArena::new();
+        let root = arena.new_node(snapshot.node);
+        append(root, snapshot.children, &mut arena);
 
-    fn into_iter() -> Self::IntoIter {
+        Tree { root, arena, counts: RefCell::default(), id: next_tree_id() }
+    }
+}
 
+/// A deep copy with the same node ids. It's a tree of its own as far as
+/// `TreeRef`s are concerned, and changing one leaves the other as it was.
+impl<'a, T: Clone> Clone for Tree<'a, T> {
+    fn clone(&self) -> Tree<'a, T> {
+        Tree {
+            root: self.root,
+            arena: self.arena.clone(),
+            counts: RefCell::default(),
+            id: next_tree_id(),
+        }
     }
-} */
+}
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Depth {
     Any,
     Some(usize),
@@ -82,160 +591,895 @@ impl PartialEq for Depth {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Node<'a> {
-    pub name: &'a str,
-    pub explanation: Option<&'a str>,
+impl From<usize> for Depth {
+    fn from(depth: usize) -> Depth {
+        Depth::Some(depth)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArgKind {
+    Int,
+    Float,
+    String,
+}
+
+/// A value a command takes right after its name, declared in the command
+/// key as `set_freq <hz:int>`. The type defaults to string when omitted.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArgSpec<'a> {
+    pub name: Cow<'a, str>,
+    pub kind: ArgKind,
+}
+
+impl<'a> ArgSpec<'a> {
+    /// Parses the part between the angle brackets, e.g. `hz:int`.
+    pub fn parse(spec: &'a str) -> Option<ArgSpec<'a>> {
+        let (name, kind) = match spec.split_once(':') {
+            Some((name, kind)) => (name.trim(), kind.trim()),
+            None => (spec.trim(), "string"),
+        };
+
+        let kind = match kind {
+            "int" => ArgKind::Int,
+            "float" => ArgKind::Float,
+            "string" => ArgKind::String,
+            _ => return None,
+        };
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            None
+        } else {
+            Some(ArgSpec { name: Cow::Borrowed(name), kind })
+        }
+    }
+
+    /// This spec with its name copied, see `Tree::into_owned`.
+    pub fn into_owned(self) -> ArgSpec<'static> {
+        ArgSpec { name: Cow::Owned(self.name.into_owned()), kind: self.kind }
+    }
+
+    pub fn accepts(&self, value: &str) -> bool {
+        match self.kind {
+            ArgKind::Int => value.parse::<i64>().is_ok(),
+            ArgKind::Float => value.parse::<f64>().is_ok(),
+            ArgKind::String => !value.is_empty(),
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for ArgSpec<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.kind {
+            ArgKind::Int => write!(f, "<{}:int>", self.name),
+            ArgKind::Float => write!(f, "<{}:float>", self.name),
+            ArgKind::String => write!(f, "<{}>", self.name),
+        }
+    }
+}
+
+/// Long-form key that lists other names for the command.
+pub const ALIASES: &str = "aliases";
+
+/// Long-form key that, set to true, hides the command from listings.
+pub const HIDDEN: &str = "hidden";
+
+/// Long-form key that, set to true, makes the command, and those below it,
+/// ask before running.
+pub const CONFIRM: &str = "confirm";
+
+/// Long-form key that sets how many seconds the command, and those below it,
+/// may take to respond.
+pub const TIMEOUT: &str = "timeout";
+
+/// Key that explains a command given in the long form, next to `CHILDREN`.
+pub const DESCRIPTION: &str = "description";
+
+/// Key that lists the subcommands of a command given in the long form.
+pub const CHILDREN: &str = "children";
+
+/// Top-level key that names a document when a file holds several.
+pub const PROFILE: &str = "profile";
+
+/// A command. Names and explanations borrow from the document the node was
+/// loaded from, or own their text after `into_owned`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Node<'a, T = ()> {
+    pub name: Cow<'a, str>,
+    pub explanation: Option<Cow<'a, str>>,
     pub depth: Depth,
+    pub arg: Option<ArgSpec<'a>>,
+    /// Other names that can be typed instead of `name`.
+    pub aliases: Vec<Cow<'a, str>>,
+    /// Still matches when typed, but is left out of usage, help and listings.
+    pub hidden: bool,
+    /// Has to be confirmed before it runs, as do the commands below it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub confirm: bool,
+    /// How long the command may take to respond, overriding any timeout set
+    /// further up the tree or by the application.
+    pub timeout: Option<Duration>,
+    /// Whatever else the application keeps per command, such as an opcode.
+    pub payload: T,
 }
 
-impl<'a> PartialEq for Node<'a> {
+impl<'a, T: PartialEq> PartialEq for Node<'a, T> {
     fn eq(&self, other: &Self) -> bool {
         (self.name == other.name) && 
         (self.explanation == other.explanation) &&
-        (self.depth == other.depth)
+        (self.depth == other.depth) &&
+        (self.arg == other.arg) &&
+        (self.aliases == other.aliases) &&
+        (self.hidden == other.hidden) &&
+        (self.confirm == other.confirm) &&
+        (self.timeout == other.timeout) &&
+        (self.payload == other.payload)
     }
 }
 
 impl<'a> Node<'a> {
-    pub fn new(name: &'a str, explanation: &'a str, depth: Depth) -> Node<'a> {
+    pub fn new(name: impl Into<Cow<'a, str>>, explanation: impl Into<Cow<'a, str>>, depth: impl Into<Depth>) -> Node<'a> {
+        let explanation = explanation.into();
         Node {
-            name,
+            name: name.into(),
             explanation: 
                 if explanation.is_empty() { None } else { Some(explanation) },
-            depth
+            depth: depth.into(),
+            arg: None,
+            aliases: Vec::new(),
+            hidden: false,
+            confirm: false,
+            timeout: None,
+            payload: (),
+        }
+    }
+
+    /// Like `new`, but `key` is a command key as authored in a translations
+    /// file and may declare an argument after the name. Returns None if that
+    /// declaration is malformed.
+    pub fn from_key(key: &'a str, explanation: &'a str, depth: impl Into<Depth>) -> Option<Node<'a>> {
+        let (name, arg) = match key.split_once('<') {
+            Some((name, spec)) => (name.trim(), Some(ArgSpec::parse(spec.trim_end().strip_suffix('>')?)?)),
+            None => (key, None),
+        };
+
+        if name.is_empty() {
+            return None;
         }
+
+        Some(Node { arg, ..Node::new(name, explanation, depth) })
     }
 
-    pub fn from_data_to_id(name: &'a str, explanation: &'a str, depth: Depth, arena: &mut NodeArena<'a>) -> NodeId {
+    pub fn from_data_to_id(name: &'a str, explanation: &'a str, depth: impl Into<Depth>, arena: &mut NodeArena<'a>) -> NodeId {
         Node::from_node_to_id(
             Node::new(name, explanation, depth),
             arena
         )
     }
 
-    pub fn from_node_to_id(node: Node<'a>, arena: &mut NodeArena<'a>) -> NodeId {
-        arena.new_node(node)
+}
+
+impl<'a, T> Node<'a, T> {
+    /// This node carrying `payload` instead.
+    pub fn with_payload<U>(self, payload: U) -> Node<'a, U> {
+        Node {
+            name: self.name,
+            explanation: self.explanation,
+            depth: self.depth,
+            arg: self.arg,
+            aliases: self.aliases,
+            hidden: self.hidden,
+            confirm: self.confirm,
+            timeout: self.timeout,
+            payload,
+        }
     }
 
-    pub fn from_id(nid: &NodeId, arena: &NodeArena<'a>) -> Node<'a> {
-        *arena.get(*nid).unwrap().get()
+    /// The name followed by any aliases.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(&*self.name).chain(self.aliases.iter().map(|alias| &**alias))
     }
-}
 
-pub mod yaml {
-    use yaml_rust::{Yaml, yaml::Hash};
-    use super::{Node, NodeId, NodeArena, Tree, Depth};
-    
-    pub fn to_tree<'a>(yaml: &'a Yaml) -> Tree {
-        let mut tree = Tree::new();
-        
-        match yaml.as_hash() {
-            Some(h) => {
-                tree.root = to_tree_rec(tree.root, &mut tree.arena, &h);
-            }
-            None => {}
-        }
-    
-        tree
-    }
-
-    fn to_tree_rec<'a>(root: NodeId, arena: &mut NodeArena<'a>, hash: &'a Hash) -> NodeId {
-        for (key, val) in hash.iter() {
-            if let Yaml::String(s) = key {
-                let root_depth = Node::from_id(&root, &arena).depth;
-                let node = Node::from_data_to_id(
-                    s, 
-                    get_exp(val), 
-                    if let Depth::Some(d) = root_depth { Depth::Some(d + 1) } else { Depth::Any }, 
-                    arena
-                );
-                root.append(node, arena);
-
-                if let Yaml::Array(vec) = val {
-                    for elem in vec {
-                        if let Yaml::Hash(h) = elem {
-                            let subroot = to_tree_rec(node, arena, h);
-                            root.append(subroot, arena);
-                        } else if let Yaml::String(s) = elem {
-                            let new_node = Node::from_data_to_id(
-                                s,
-                                "",
-                                if let Depth::Some(d) = root_depth { Depth::Some(d + 1) } else { Depth::Any }, 
-                                arena
-                            );
-                            node.append(new_node, arena);
-                        }
-                    }
-                }
-            }
+    /// This node with its names, explanation and argument copied, so that it
+    /// no longer borrows from anything.
+    pub fn into_owned(self) -> Node<'static, T> {
+        Node {
+            name: Cow::Owned(self.name.into_owned()),
+            explanation: self.explanation.map(|exp| Cow::Owned(exp.into_owned())),
+            depth: self.depth,
+            arg: self.arg.map(ArgSpec::into_owned),
+            aliases: self.aliases.into_iter().map(|alias| Cow::Owned(alias.into_owned())).collect(),
+            hidden: self.hidden,
+            confirm: self.confirm,
+            timeout: self.timeout,
+            payload: self.payload,
         }
-        root
     }
 
-    fn get_exp(yaml: &Yaml) -> &str {
-        if let Yaml::String(exp) = yaml {
-            exp
-        } else {
-            ""
+    // A copy carrying `payload`, for `Tree::map_payloads`
+    fn cloned_with<U>(&self, payload: U) -> Node<'a, U> {
+        Node {
+            name: self.name.clone(),
+            explanation: self.explanation.clone(),
+            depth: self.depth,
+            arg: self.arg.clone(),
+            aliases: self.aliases.clone(),
+            hidden: self.hidden,
+            confirm: self.confirm,
+            timeout: self.timeout,
+            payload,
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    pub fn from_node_to_id(node: Node<'a, T>, arena: &mut NodeArena<'a, T>) -> NodeId {
+        arena.new_node(node)
+    }
+}
 
-    mod nodes {
-        use super::*;
+impl<'a, T: Clone> Node<'a, T> {
+    /// A copy of the node at `nid`.
+    ///
+    /// # Panics
+    ///
+    /// If `nid` isn't in `arena`, e.g. because it was removed or belongs to
+    /// another tree. Use `try_from_id` where that can happen.
+    pub fn from_id(nid: &NodeId, arena: &NodeArena<'a, T>) -> Node<'a, T> {
+        Node::try_from_id(nid, arena).expect("node id is not in this arena")
+    }
 
-        #[test]
-        fn same_empty() {
-            let node1 = Node::new("", "", 0);
-            let node2 = Node::new("", "", 0);
+    /// Like `from_id`, but None for ids of removed nodes and ids past the end
+    /// of `arena`. An id from another tree that happens to be in range still
+    /// finds whichever node sits there.
+    pub fn try_from_id(nid: &NodeId, arena: &NodeArena<'a, T>) -> Option<Node<'a, T>> {
+        arena.get(*nid).and_then(|node| node.try_get()).cloned()
+    }
+}
 
-            assert_eq!(node1, node2);
-        }
+/// The walk shared by the loaders. Commands are laid out the same way in
+/// every format, see `yaml`, so each only says how to read its own values.
+mod document {
+    use super::{Node, NodeId, NodeArena, Tree, Depth, TranslatorError, ALIASES, CHILDREN, CONFIRM, DESCRIPTION, HIDDEN, PROFILE, TIMEOUT};
 
-        #[test]
-        fn same() {
-            let node1 = Node::new("node1", "exp", 1);
-            let node2 = Node::new("node1", "exp", 1);
+    /// A map key: a command or field name, or the type of a key that can't
+    /// be one.
+    pub(super) type Key<'a> = std::result::Result<&'a str, &'static str>;
 
-            assert_eq!(node1, node2)
-        }
+    /// A parsed value of some document format.
+    pub(super) trait Document: Sized {
+        /// What the top level is expected to be, e.g. "a map of commands".
+        const COMMANDS: &'static str;
+        /// What a subcommand list element is expected to be.
+        const ELEMENT: &'static str;
+        /// Whether a top-level `profile` key names the document instead of
+        /// declaring a command.
+        const PROFILES: bool = false;
 
-        #[test]
-        fn different_names() {
-            let node1 = Node::new("node1", "exp1", 1);
-            let node2 = Node::new("", "exp1", 1);
-            
-            assert_ne!(node1, node2);
-        }
+        fn entries(&self) -> Option<Vec<(Key<'_>, &Self)>>;
+        fn string(&self) -> Option<&str>;
+        fn list(&self) -> Option<&[Self]>;
+        fn boolean(&self) -> Option<bool>;
+        fn number(&self) -> Option<f64>;
+        fn is_null(&self) -> bool;
+        fn type_name(&self) -> &'static str;
+    }
 
-        #[test]
-        fn differnet_exps() {
-            let node1 = Node::new("node1", "exp1", 1);
-            let node2 = Node::new("node1", "", 1);
-            
-            assert_ne!(node1, node2);
-        }
+    pub(super) fn to_tree<D: Document>(doc: &D, max_depth: usize) -> Result<Tree<'_>, TranslatorError> {
+        let mut tree = Tree::new();
 
-        #[test]
-        fn different_depths() {
-            let node1 = Node::new("node1", "exp1", 0);
-            let node2 = Node::new("node1", "exp1", 1);
-            
-            assert_ne!(node1, node2);
+        match doc.entries() {
+            Some(entries) => {
+                tree.root = to_tree_rec(tree.root, &mut tree.arena, entries, "", 0, max_depth)?;
+            }
+            None => return Err(unexpected("", D::COMMANDS, doc)),
         }
 
+        tree.validate()?;
+        Ok(tree)
     }
 
-    mod tree {
-        use super::*;
+    // `level` is how far `root` is below the tree root
+    fn to_tree_rec<'a, D: Document>(
+        root: NodeId,
+        arena: &mut NodeArena<'a>,
+        entries: Vec<(Key<'a>, &'a D)>,
+        path: &str,
+        level: usize,
+        max_depth: usize,
+    ) -> Result<NodeId, TranslatorError> {
+        for (key, val) in entries {
+            let s = key.map_err(|found| TranslatorError::UnexpectedType {
+                path: path.to_string(),
+                expected: "a command name",
+                found,
+            })?;
+            let key_path = if path.is_empty() { s.to_string() } else { format!("{}/{}", path, s) };
 
-        impl<'a> PartialEq for Tree<'a> {
-            fn eq(&self, other: &Self) -> bool {
+            if D::PROFILES && path.is_empty() && s == PROFILE {
+                continue;
+            }
+
+            let (exp, children, children_path, options) = if let Some(list) = val.list() {
+                ("", Some(list), key_path.clone(), None)
+            } else if let Some(exp) = val.string() {
+                (exp, None, key_path.clone(), None)
+            } else if val.is_null() {
+                // A bare key without an explanation
+                ("", None, key_path.clone(), None)
+            } else if let Some(fields) = val.entries() {
+                let (exp, children) = described(&fields, val, &key_path)?;
+                (exp, children, format!("{}/{}", key_path, CHILDREN), Some(fields))
+            } else {
+                return Err(unexpected(&key_path, "an explanation or a list of commands", val));
+            };
+
+            let too_deep = match children {
+                Some(children) if !children.is_empty() => level + 2 > max_depth,
+                _ => level + 1 > max_depth,
+            };
+            if too_deep {
+                return Err(TranslatorError::TooDeep { path: key_path, max: max_depth });
+            }
+
+            let root_depth = Node::from_id(&root, arena).depth;
+            let node = Node::from_key(
+                s,
+                exp,
+                if let Depth::Some(d) = root_depth { Depth::Some(d + 1) } else { Depth::Any },
+            ).ok_or_else(|| invalid_arg(&key_path, s))?;
+            let node = Node::from_node_to_id(node, arena);
+            root.append(node, arena);
+            if let Some(fields) = options {
+                set_options(node, arena, &fields, &key_path)?;
+            }
+
+            for (i, elem) in children.into_iter().flatten().enumerate() {
+                let elem_path = format!("{}[{}]", children_path, i);
+
+                if let Some(entries) = elem.entries() {
+                    to_tree_rec(node, arena, entries, &elem_path, level + 1, max_depth)?;
+                } else if let Some(s) = elem.string() {
+                    let new_node = Node::from_key(
+                        s,
+                        "",
+                        if let Depth::Some(d) = root_depth { Depth::Some(d + 2) } else { Depth::Any },
+                    ).ok_or_else(|| invalid_arg(&elem_path, s))?;
+                    let new_node = Node::from_node_to_id(new_node, arena);
+                    node.append(new_node, arena);
+                } else {
+                    return Err(unexpected(&elem_path, D::ELEMENT, elem));
+                }
+            }
+        }
+        Ok(root)
+    }
+
+    /// The long form of a command, for one that has both an explanation and
+    /// subcommands or any options: a map with a `description`, a list of
+    /// `children` and the `aliases`, `hidden`, `confirm` and `timeout`
+    /// options, all of which may be left out.
+    fn described<'a, D: Document>(
+        fields: &[(Key<'a>, &'a D)],
+        val: &D,
+        path: &str,
+    ) -> Result<(&'a str, Option<&'a [D]>), TranslatorError> {
+        let mut exp = "";
+        let mut children = None;
+
+        for &(key, field) in fields {
+            let field_path = format!("{}/{}", path, key.unwrap_or_default());
+
+            match key {
+                Ok(DESCRIPTION | CHILDREN) if field.is_null() => {}
+                Ok(DESCRIPTION) => {
+                    exp = field.string().ok_or_else(|| unexpected(&field_path, "an explanation", field))?;
+                }
+                Ok(CHILDREN) => {
+                    children = Some(field.list().ok_or_else(|| unexpected(&field_path, "a list of commands", field))?);
+                }
+                // Set by `set_options` once the command's node exists
+                Ok(ALIASES | HIDDEN | CONFIRM | TIMEOUT) => {}
+                _ => return Err(unexpected(path, "an explanation or a list of commands", val)),
+            }
+        }
+
+        Ok((exp, children))
+    }
+
+    /// Sets the options given in the long form of the command at `node`.
+    /// `described` has already turned away any other keys.
+    fn set_options<'a, D: Document>(
+        node: NodeId,
+        arena: &mut NodeArena<'a>,
+        fields: &[(Key<'a>, &'a D)],
+        path: &str,
+    ) -> Result<(), TranslatorError> {
+        for &(key, val) in fields {
+            let key = key.unwrap_or_default();
+            let key_path = format!("{}/{}", path, key);
+
+            if key == ALIASES {
+                add_aliases(node, arena, val, &key_path)?;
+            } else if key == HIDDEN {
+                arena[node].get_mut().hidden = val
+                    .boolean()
+                    .ok_or_else(|| unexpected(&key_path, "true or false", val))?;
+            } else if key == CONFIRM {
+                arena[node].get_mut().confirm = val
+                    .boolean()
+                    .ok_or_else(|| unexpected(&key_path, "true or false", val))?;
+            } else if key == TIMEOUT {
+                arena[node].get_mut().timeout = Some(
+                    val.number()
+                        .and_then(super::seconds)
+                        .ok_or_else(|| unexpected(&key_path, "a timeout in seconds", val))?
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_aliases<'a, D: Document>(node: NodeId, arena: &mut NodeArena<'a>, val: &'a D, path: &str) -> Result<(), TranslatorError> {
+        let aliases = val.list().ok_or_else(|| unexpected(path, "a list of aliases", val))?;
+
+        for (i, alias) in aliases.iter().enumerate() {
+            match alias.string() {
+                Some(alias) => arena[node].get_mut().aliases.push(alias.into()),
+                None => return Err(unexpected(&format!("{}[{}]", path, i), "an alias", alias)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalid_arg(path: &str, key: &str) -> TranslatorError {
+        TranslatorError::InvalidArgument {
+            path: path.to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    fn unexpected<D: Document>(path: &str, expected: &'static str, found: &D) -> TranslatorError {
+        TranslatorError::UnexpectedType {
+            path: path.to_string(),
+            expected,
+            found: found.type_name(),
+        }
+    }
+}
+
+/// A key is a command and a string value its explanation. Subcommands go
+/// in a list of single-entry maps, or plain strings for those without an
+/// explanation. A map value is the long form, with a `description`,
+/// `children` and the options:
+///
+/// ```yaml
+/// sat:
+/// - obc:
+///   - ping: 'Ping the obc'
+///   - set
+/// - reboot:
+///     description: 'Reboot the satellite'
+///     confirm: true
+///     timeout: 30
+/// ```
+///
+/// `aliases`, `hidden`, `confirm` and `timeout` used to be list entries
+/// next to the subcommands, as in `reboot: [{confirm: true}]`, which kept
+/// commands from having those names. They now only count in the long form,
+/// so such documents move the entries into it: `reboot: {confirm: true}`,
+/// with any subcommands under `children`. Left as they were, they load as
+/// subcommands and fail, e.g. `Expected an explanation or a list of commands
+/// at reboot[0]/confirm, found boolean`.
+pub mod yaml {
+    use yaml_rust::{Yaml, YamlEmitter, yaml::Hash};
+    use std::time::Duration;
+    use super::document::{self, Document, Key};
+    use super::{Node, NodeId, NodeArena, Tree, TranslatorError, ALIASES, CHILDREN, CONFIRM, DESCRIPTION, HIDDEN, MAX_DEPTH, PROFILE, TIMEOUT};
+    
+    /// Yaml values carry no source positions, so errors locate the offending
+    /// value by its key path instead, e.g. `sat[0]/obc[2]`.
+    pub fn to_tree<'a>(yaml: &'a Yaml) -> Result<Tree<'a>, TranslatorError> {
+        to_tree_max_depth(yaml, MAX_DEPTH)
+    }
+
+    /// Like `to_tree`, but fails with `TranslatorError::TooDeep` for commands
+    /// more than `max_depth` levels below the root instead of at `MAX_DEPTH`.
+    pub fn to_tree_max_depth<'a>(yaml: &'a Yaml, max_depth: usize) -> Result<Tree<'a>, TranslatorError> {
+        document::to_tree(yaml, max_depth)
+    }
+
+    /// Like `to_tree`, with each node's payload set by `payload`, as in
+    /// `Tree::map_payloads`.
+    pub fn to_tree_with<'a, T>(
+        yaml: &'a Yaml,
+        payload: impl FnMut(&[&str], &Node<'a>) -> T,
+    ) -> Result<Tree<'a, T>, TranslatorError> {
+        Ok(to_tree(yaml)?.map_payloads(payload))
+    }
+
+    /// Picks one of several documents, e.g. from a file with one `---`
+    /// separated document per spacecraft, and builds its tree. A document is
+    /// named by a top-level `profile: <name>` key. Profiles without a matching
+    /// name can also be picked by their position, counting from 0.
+    pub fn profile_to_tree<'a>(docs: &'a [Yaml], profile: &str) -> Result<Tree<'a>, TranslatorError> {
+        let named = docs
+            .iter()
+            .find(|doc| doc[PROFILE].as_str() == Some(profile));
+        let doc = named.or_else(|| profile.parse().ok().and_then(|i: usize| docs.get(i)));
+
+        match doc {
+            Some(doc) => to_tree(doc),
+            None => Err(TranslatorError::NoSuchProfile(profile.to_string())),
+        }
+    }
+
+    /// The inverse of `to_tree`: a document that parses back into `tree`.
+    /// Commands that have both an explanation and subcommands, or any of
+    /// aliases, a hidden or confirm marker and a timeout, are written in the
+    /// long form with `description` and `children`.
+    pub fn from_tree(tree: &Tree) -> String {
+        let mut doc = Hash::new();
+        for child in tree.root.children(&tree.arena) {
+            let (key, val) = from_node(child, &tree.arena);
+            doc.insert(key, val);
+        }
+
+        let mut out = String::new();
+        // Writing to a String can't fail
+        YamlEmitter::new(&mut out).dump(&Yaml::Hash(doc)).unwrap();
+        out.push('\n');
+        out
+    }
+
+    fn from_node(nid: NodeId, arena: &NodeArena) -> (Yaml, Yaml) {
+        let node = Node::from_id(&nid, arena);
+        let key = match &node.arg {
+            Some(arg) => format!("{} {}", node.name, arg),
+            None => node.name.to_string(),
+        };
+
+        // Subcommands are a list of single-entry maps, as in translations.yml
+        let mut subcmds = Vec::new();
+        for child in nid.children(arena) {
+            let (key, val) = from_node(child, arena);
+            subcmds.push(single_entry(key, val));
+        }
+
+        // Options only have a place in the long form
+        let mut options = Vec::new();
+        if !node.aliases.is_empty() {
+            let aliases = node.aliases.iter().map(|a| Yaml::String(a.to_string())).collect();
+            options.push((ALIASES, Yaml::Array(aliases)));
+        }
+        if node.hidden {
+            options.push((HIDDEN, Yaml::Boolean(true)));
+        }
+        if node.confirm {
+            options.push((CONFIRM, Yaml::Boolean(true)));
+        }
+        if let Some(timeout) = node.timeout {
+            options.push((TIMEOUT, seconds_yaml(timeout)));
+        }
+
+        let val = if !options.is_empty() || (node.explanation.is_some() && !subcmds.is_empty()) {
+            let mut described = Hash::new();
+            if let Some(exp) = &node.explanation {
+                described.insert(Yaml::String(DESCRIPTION.to_string()), Yaml::String(exp.to_string()));
+            }
+            for (key, option) in options {
+                described.insert(Yaml::String(key.to_string()), option);
+            }
+            if !subcmds.is_empty() {
+                described.insert(Yaml::String(CHILDREN.to_string()), Yaml::Array(subcmds));
+            }
+            Yaml::Hash(described)
+        } else if !subcmds.is_empty() {
+            Yaml::Array(subcmds)
+        } else {
+            match &node.explanation {
+                Some(exp) => Yaml::String(exp.to_string()),
+                None => Yaml::Null,
+            }
+        };
+
+        (Yaml::String(key), val)
+    }
+
+    fn seconds_yaml(timeout: Duration) -> Yaml {
+        let seconds = timeout.as_secs_f64();
+        if seconds.fract() == 0.0 {
+            Yaml::Integer(seconds as i64)
+        } else {
+            Yaml::Real(seconds.to_string())
+        }
+    }
+
+    fn single_entry(key: Yaml, val: Yaml) -> Yaml {
+        let mut entry = Hash::new();
+        entry.insert(key, val);
+        Yaml::Hash(entry)
+    }
+
+    impl Document for Yaml {
+        const COMMANDS: &'static str = "a map of commands";
+        const ELEMENT: &'static str = "a command name or a map";
+        const PROFILES: bool = true;
+
+        fn entries(&self) -> Option<Vec<(Key<'_>, &Yaml)>> {
+            let hash = self.as_hash()?;
+            Some(hash.iter().map(|(key, val)| (key.as_str().ok_or_else(|| key.type_name()), val)).collect())
+        }
+
+        fn string(&self) -> Option<&str> {
+            self.as_str()
+        }
+
+        fn list(&self) -> Option<&[Yaml]> {
+            self.as_vec().map(Vec::as_slice)
+        }
+
+        fn boolean(&self) -> Option<bool> {
+            self.as_bool()
+        }
+
+        fn number(&self) -> Option<f64> {
+            match self {
+                Yaml::Integer(i) => Some(*i as f64),
+                _ => self.as_f64(),
+            }
+        }
+
+        fn is_null(&self) -> bool {
+            Yaml::is_null(self)
+        }
+
+        fn type_name(&self) -> &'static str {
+            match self {
+                Yaml::Real(_) => "float",
+                Yaml::Integer(_) => "integer",
+                Yaml::String(_) => "string",
+                Yaml::Boolean(_) => "boolean",
+                Yaml::Array(_) => "list",
+                Yaml::Hash(_) => "map",
+                Yaml::Alias(_) => "alias",
+                Yaml::Null => "null",
+                Yaml::BadValue => "invalid value",
+            }
+        }
+    }
+}
+
+pub mod json {
+    use serde_json::Value;
+    use super::document::{self, Document, Key};
+    use super::{Tree, TranslatorError, MAX_DEPTH};
+
+    /// Builds a tree from a document laid out like the YAML one, failing
+    /// the same way `yaml::to_tree` does. Errors locate the offending value
+    /// by its key path, e.g. `sat[0]/obc[2]`.
+    pub fn to_tree(json: &Value) -> Result<Tree<'_>, TranslatorError> {
+        to_tree_max_depth(json, MAX_DEPTH)
+    }
+
+    /// Like `to_tree`, but fails with `TranslatorError::TooDeep` for commands
+    /// more than `max_depth` levels below the root instead of at `MAX_DEPTH`.
+    pub fn to_tree_max_depth(json: &Value, max_depth: usize) -> Result<Tree<'_>, TranslatorError> {
+        document::to_tree(json, max_depth)
+    }
+
+    impl Document for Value {
+        const COMMANDS: &'static str = "a map of commands";
+        const ELEMENT: &'static str = "a command name or an object";
+
+        fn entries(&self) -> Option<Vec<(Key<'_>, &Value)>> {
+            let map = self.as_object()?;
+            Some(map.iter().map(|(key, val)| (Ok(key.as_str()), val)).collect())
+        }
+
+        fn string(&self) -> Option<&str> {
+            self.as_str()
+        }
+
+        fn list(&self) -> Option<&[Value]> {
+            self.as_array().map(Vec::as_slice)
+        }
+
+        fn boolean(&self) -> Option<bool> {
+            self.as_bool()
+        }
+
+        fn number(&self) -> Option<f64> {
+            self.as_f64()
+        }
+
+        fn is_null(&self) -> bool {
+            Value::is_null(self)
+        }
+
+        fn type_name(&self) -> &'static str {
+            match self {
+                Value::Null => "null",
+                Value::Bool(_) => "boolean",
+                Value::Number(n) if n.is_f64() => "float",
+                Value::Number(_) => "integer",
+                Value::String(_) => "string",
+                Value::Array(_) => "list",
+                Value::Object(_) => "object",
+            }
+        }
+    }
+}
+
+/// Commands in TOML follow the YAML layout: a key is a command and a string
+/// value its explanation. Subcommands go in an array, most readably built
+/// with array-of-tables headers, and a table is the long form with a
+/// `description`, `children` and the options:
+///
+/// ```toml
+/// gs = "Ground station"
+///
+/// [[sat]]
+/// [[sat.obc]]
+/// ping = "Ping the obc"
+/// set = ""
+///
+/// [sat.reboot]
+/// description = "Reboot the satellite"
+/// confirm = true
+/// ```
+///
+/// Array elements can also be plain strings for subcommands without an
+/// explanation, as in `obc = ["ping", "set"]`.
+pub mod toml {
+    use ::toml::Value;
+    use super::document::{self, Document, Key};
+    use super::{Tree, TranslatorError, MAX_DEPTH};
+
+    /// Builds a tree, failing the same way `yaml::to_tree` does. Errors
+    /// locate the offending value by its key path, e.g. `sat[0]/obc[2]`.
+    pub fn to_tree(toml: &Value) -> Result<Tree<'_>, TranslatorError> {
+        to_tree_max_depth(toml, MAX_DEPTH)
+    }
+
+    /// Like `to_tree`, but fails with `TranslatorError::TooDeep` for commands
+    /// more than `max_depth` levels below the root instead of at `MAX_DEPTH`.
+    pub fn to_tree_max_depth(toml: &Value, max_depth: usize) -> Result<Tree<'_>, TranslatorError> {
+        document::to_tree(toml, max_depth)
+    }
+
+    impl Document for Value {
+        const COMMANDS: &'static str = "a table of commands";
+        const ELEMENT: &'static str = "a command name or a table";
+
+        fn entries(&self) -> Option<Vec<(Key<'_>, &Value)>> {
+            let table = self.as_table()?;
+            Some(table.iter().map(|(key, val)| (Ok(key.as_str()), val)).collect())
+        }
+
+        fn string(&self) -> Option<&str> {
+            self.as_str()
+        }
+
+        fn list(&self) -> Option<&[Value]> {
+            self.as_array().map(Vec::as_slice)
+        }
+
+        fn boolean(&self) -> Option<bool> {
+            self.as_bool()
+        }
+
+        fn number(&self) -> Option<f64> {
+            self.as_float().or_else(|| self.as_integer().map(|i| i as f64))
+        }
+
+        // TOML has no null
+        fn is_null(&self) -> bool {
+            false
+        }
+
+        fn type_name(&self) -> &'static str {
+            self.type_str()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod nodes {
+        use super::*;
+
+        #[test]
+        fn same_empty() {
+            let node1 = Node::new("", "", 0);
+            let node2 = Node::new("", "", 0);
+
+            assert_eq!(node1, node2);
+        }
+
+        #[test]
+        fn same() {
+            let node1 = Node::new("node1", "exp", 1);
+            let node2 = Node::new("node1", "exp", 1);
+
+            assert_eq!(node1, node2)
+        }
+
+        #[test]
+        fn different_names() {
+            let node1 = Node::new("node1", "exp1", 1);
+            let node2 = Node::new("", "exp1", 1);
+            
+            assert_ne!(node1, node2);
+        }
+
+        #[test]
+        fn differnet_exps() {
+            let node1 = Node::new("node1", "exp1", 1);
+            let node2 = Node::new("node1", "", 1);
+            
+            assert_ne!(node1, node2);
+        }
+
+        #[test]
+        fn depth_from_usize() {
+            assert!(matches!(Node::new("node", "", 3).depth, Depth::Some(3)));
+            assert!(matches!(Node::new("node", "", Depth::Any).depth, Depth::Any));
+        }
+
+        #[test]
+        fn from_key() {
+            let node = Node::from_key("set_freq <hz:int>", "", 1).unwrap();
+            assert_eq!(node.name, "set_freq");
+            assert_eq!(node.arg, Some(ArgSpec { name: "hz".into(), kind: ArgKind::Int }));
+
+            let node = Node::from_key("gain <db : float>", "", 1).unwrap();
+            assert_eq!(node.name, "gain");
+            assert_eq!(node.arg, Some(ArgSpec { name: "db".into(), kind: ArgKind::Float }));
+
+            assert_eq!(Node::from_key("ping", "exp", 1), Some(Node::new("ping", "exp", 1)));
+            assert_eq!(Node::from_key("set_freq <hz:int", "", 1), None);
+            assert_eq!(Node::from_key("set_freq <>", "", 1), None);
+            assert_eq!(Node::from_key("<hz>", "", 1), None);
+            assert_eq!(Node::from_key("set <hz:bool>", "", 1), None);
+        }
+
+        #[test]
+        fn arg_accepts() {
+            let int = ArgSpec { name: "hz".into(), kind: ArgKind::Int };
+            let float = ArgSpec { name: "db".into(), kind: ArgKind::Float };
+            let string = ArgSpec { name: "name".into(), kind: ArgKind::String };
+
+            assert!(int.accepts("433") && int.accepts("-1"));
+            assert!(!int.accepts("4.5") && !int.accepts("abc"));
+            assert!(float.accepts("4.5") && float.accepts("3"));
+            assert!(!float.accepts("abc"));
+            assert!(string.accepts("abc") && !string.accepts(""));
+
+            assert_eq!(int.to_string(), "<hz:int>");
+            assert_eq!(string.to_string(), "<name>");
+        }
+
+        #[test]
+        fn different_depths() {
+            let node1 = Node::new("node1", "exp1", 0);
+            let node2 = Node::new("node1", "exp1", 1);
+            
+            assert_ne!(node1, node2);
+        }
+
+    }
+
+    mod tree {
+        use super::*;
+
+        impl<'a> PartialEq for Tree<'a> {
+            fn eq(&self, other: &Self) -> bool {
                 for (nid1, nid2) in self.root.descendants(&self.arena).zip(other.root.descendants(&other.arena)) {
                     let (n1, n2) = (
                         Node::from_id(&nid1, &self.arena),
@@ -275,11 +1519,26 @@ mod tests {
         #[test]
         fn new_depth() {
             let tree = Tree::new_depth(5);
-            let tree2 = Tree::new_depth(5);
+            let tree2 = Tree::new_depth(Depth::Some(5));
             assert_eq!(
                 tree,
                 tree2
             );
+            assert!(matches!(Node::from_id(&tree.root, &tree.arena).depth, Depth::Some(5)));
+
+            let mut any = Tree::new_depth(Depth::Any);
+            let ping = any.insert_at_path(&["sat"], Node::new("ping", "", 0));
+            assert!(matches!(Node::from_id(&ping, &any.arena).depth, Depth::Any));
+        }
+
+        #[test]
+        fn grafted_depths() {
+            let mut tree = Tree::new_depth(3);
+            let ping = tree.insert_at_path(&["sat", "obc"], Node::new("ping", "", 0));
+            let sat = tree.find_by_path(&["sat"]).unwrap();
+
+            assert!(matches!(Node::from_id(&sat, &tree.arena).depth, Depth::Some(4)));
+            assert!(matches!(Node::from_id(&ping, &tree.arena).depth, Depth::Some(6)));
         }
     
         #[test]
@@ -316,46 +1575,265 @@ mod tests {
         }
 
         #[test]
-        fn different_depths() {
-            let tree1 = generate_tree(vec![("node", "exp", 0)]);
-            let tree2 = generate_tree(vec![("node", "exp", 1)]);
+        fn cached_subtree_count() {
+            let mut tree = generate_tree(vec![("sat", "", 1), ("gs", "", 1)]);
+            let sat = tree.root.children(&tree.arena).next().unwrap();
+            for name in ["obc", "adcs", "pay"] {
+                let node = Node::from_data_to_id(name, "", Depth::Some(2), &mut tree.arena);
+                sat.append(node, &mut tree.arena);
+            }
 
-            assert_ne!(tree1, tree2);
+            for node in tree.root.descendants(&tree.arena) {
+                assert_eq!(tree.subtree_count(&node), subtree_count(&node, &tree.arena));
+            }
+            assert_eq!(tree.subtree_count(&tree.root), 5);
+            assert_eq!(tree.subtree_count(&sat), 3);
+
+            // Adding nodes refreshes the cache
+            let obc = sat.children(&tree.arena).next().unwrap();
+            let ping = Node::from_data_to_id("ping", "", Depth::Some(3), &mut tree.arena);
+            obc.append(ping, &mut tree.arena);
+            assert_eq!(tree.subtree_count(&tree.root), 6);
+            assert_eq!(tree.subtree_count(&obc), 1);
+
+            // Moving nodes needs an explicit invalidation
+            let gs = tree.root.children(&tree.arena).nth(1).unwrap();
+            ping.detach(&mut tree.arena);
+            gs.append(ping, &mut tree.arena);
+            tree.invalidate_counts();
+            assert_eq!(tree.subtree_count(&obc), 0);
+            assert_eq!(tree.subtree_count(&gs), 1);
         }
-    }
 
-    mod yaml {
-        use super::*;
-        use crate::yaml::to_tree;
-        use yaml_rust::YamlLoader;
-        
-        const YAMLDOC: &str =
-        "
-        node1:
-        - subnode1:
-          - subsubnode1:
-            'subsubnode1 explanation'
-          - subsubnode2:
-            'subsubnode2 explanation'
-        - subnode2:
-            'subnode2 explanation'
-        node2:
-          'node2 explanation'
-        node3:
-          - subnode1:
-            'subnode1 explanation'
-        ";
+        #[test]
+        fn children() {
+            let yaml = yaml_rust::YamlLoader::load_from_str("sat:\n- obc:\n  - ping\n  - set\n- pay\ngs:").unwrap();
+            let tree = crate::yaml::to_tree(&yaml[0]).unwrap();
+            let node = |path: &[&str]| tree.find_by_path(path).unwrap();
+
+            assert_eq!(tree.child_count(tree.root), 2);
+            assert_eq!(tree.child_count(node(&["sat"])), 2);
+            assert_eq!(tree.subtree_count(&node(&["sat"])), 4);
+            assert_eq!(tree.child_count(node(&["sat", "obc", "ping"])), 0);
 
+            assert!(!tree.is_leaf(tree.root));
+            assert!(!tree.is_leaf(node(&["sat", "obc"])));
+            assert!(tree.is_leaf(node(&["sat", "obc", "ping"])));
+            assert!(tree.is_leaf(node(&["sat", "pay"])));
+            assert!(tree.is_leaf(node(&["gs"])));
+        }
 
         #[test]
-        fn tree() {
-            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
-            let tree = yaml::to_tree(&yaml[0]);
+        fn leaves() {
+            let yaml = yaml_rust::YamlLoader::load_from_str(super::yaml::YAMLDOC).unwrap();
+            let tree = crate::yaml::to_tree(&yaml[0]).unwrap();
 
-            let nodes = vec![
-                Node::new("root", "", 0),
-                Node::new("node1", "", 1),
-                Node::new("subnode1", "", 2),
+            let paths: Vec<String> = tree.leaves().map(|leaf| tree.path_of(leaf).join("/")).collect();
+            assert_eq!(paths, vec![
+                "node1/subnode1/subsubnode1",
+                "node1/subnode1/subsubnode2",
+                "node1/subnode2",
+                "node2",
+                "node3/subnode1",
+            ]);
+            assert!(tree.path_of(tree.root).is_empty());
+            assert_eq!(Tree::<()>::new().leaves().count(), 0);
+        }
+
+        #[test]
+        fn find_by_path() {
+            let mut tree = generate_tree(vec![("sat", "", 1), ("gs", "", 1)]);
+            let sat = tree.root.children(&tree.arena).next().unwrap();
+            let obc = Node::from_data_to_id("obc", "", Depth::Some(2), &mut tree.arena);
+            sat.append(obc, &mut tree.arena);
+
+            assert_eq!(tree.find_by_path(&["sat"]), Some(sat));
+            assert_eq!(tree.find_by_path(&["sat", "obc"]), Some(obc));
+            assert_eq!(tree.find_by_path(&["sat", "adcs"]), None);
+            assert_eq!(tree.find_by_path(&["obc"]), None);
+            assert_eq!(tree.find_by_path(&[]), Some(tree.root));
+            assert_eq!(tree.find_by_path(&["SAT", "Obc"]), None);
+            assert_eq!(tree.find_by_path_ignore_case(&["SAT", "Obc"]), Some(obc));
+        }
+
+        #[test]
+        fn find_by_path_from() {
+            let mut tree = generate_tree(vec![("sat", "", 1), ("gs", "", 1)]);
+            let sat = tree.root.children(&tree.arena).next().unwrap();
+            let obc = Node::from_data_to_id("obc", "", Depth::Some(2), &mut tree.arena);
+            sat.append(obc, &mut tree.arena);
+            let ping = Node::from_data_to_id("ping", "", Depth::Some(3), &mut tree.arena);
+            obc.append(ping, &mut tree.arena);
+
+            assert_eq!(tree.find_by_path_from(sat, &["obc", "ping"]), Some(ping));
+            assert_eq!(tree.find_by_path_from(sat, &[]), Some(sat));
+            assert_eq!(tree.find_by_path_from(sat, &["sat"]), None);
+            assert_eq!(tree.find_by_path_from(tree.root, &["sat", "obc", "ping"]), Some(ping));
+            assert_eq!(tree.find_by_path_from(tree.root, &["sat", "adcs", "ping"]), None);
+            assert_eq!(tree.find_by_path_from(tree.root, &"sat/obc".split('/').collect::<Vec<_>>()), Some(obc));
+        }
+
+        #[test]
+        fn insert_at_path() {
+            let mut tree = Tree::new();
+            let ping = tree.insert_at_path(&["sat", "obc"], Node::new("ping", "Ping the obc", 7));
+            let set = tree.insert_at_path(&["sat", "obc"], Node::new("set", "", 0));
+            let gs = tree.insert_at_path(&[], Node::new("gs", "", 0));
+
+            let yaml = yaml_rust::YamlLoader::load_from_str("sat:\n- obc:\n  - ping: 'Ping the obc'\n  - set\ngs:").unwrap();
+            let expected = crate::yaml::to_tree(&yaml[0]).unwrap();
+
+            assert_eq!(tree.iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+            assert_eq!(tree.find_by_path(&["sat", "obc", "ping"]), Some(ping));
+            assert_eq!(tree.find_by_path(&["sat", "obc", "set"]), Some(set));
+            assert_eq!(tree.find_by_path(&["gs"]), Some(gs));
+            assert_eq!(tree.subtree_count(&tree.root), 5);
+        }
+
+        #[test]
+        fn tree_refs() {
+            let mut tree = Tree::new();
+            let sat = tree.insert_at_path(&[], Node::new("sat", "", 1));
+            let other: Tree = Tree::new();
+
+            let sat_ref = tree.node_ref(sat);
+            assert!(tree.owns(sat_ref));
+            assert!(!other.owns(sat_ref));
+            assert_eq!(tree.resolve(sat_ref), sat);
+            assert!(!tree.owns(tree.map_payloads(|_, _| ()).node_ref(sat)));
+        }
+
+        #[test]
+        #[should_panic(expected = "belongs to another tree")]
+        fn crossed_tree_ref() {
+            let tree: Tree = Tree::new();
+            let other: Tree = Tree::new();
+            other.resolve(tree.node_ref(tree.root));
+        }
+
+        #[test]
+        fn try_from_id() {
+            let mut tree = Tree::new();
+            let sat = tree.insert_at_path(&[], Node::new("sat", "", 1));
+            let ping = tree.insert_at_path(&["sat"], Node::new("ping", "", 2));
+            assert_eq!(Node::try_from_id(&ping, &tree.arena), Some(Node::new("ping", "", 2)));
+
+            tree.remove(sat);
+            assert_eq!(Node::try_from_id(&ping, &tree.arena), None);
+
+            let small: Tree = Tree::new();
+            assert_eq!(Node::try_from_id(&ping, &small.arena), None);
+        }
+
+        #[test]
+        fn remove() {
+            let yaml = yaml_rust::YamlLoader::load_from_str(
+                "sat:\n- obc:\n  - ping\n- pay:\n  - ping\n  - take_pic\ngs:"
+            ).unwrap();
+            let mut tree = crate::yaml::to_tree(&yaml[0]).unwrap();
+            let pay = tree.find_by_path(&["sat", "pay"]).unwrap();
+            let take_pic = tree.find_by_path(&["sat", "pay", "take_pic"]).unwrap();
+            assert_eq!(tree.subtree_count(&tree.root), 7);
+
+            tree.remove(take_pic);
+            assert_eq!(tree.find_by_path(&["sat", "pay", "take_pic"]), None);
+            assert_eq!(tree.subtree_count(&pay), 1);
+            assert_eq!(tree.subtree_count(&tree.root), 6);
+
+            // Reuses the removed slot, which the cache has to notice too
+            tree.insert_at_path(&["sat", "pay"], Node::new("take_pic", "", 0));
+            assert_eq!(tree.subtree_count(&pay), 2);
+
+            tree.remove(pay);
+            assert_eq!(tree.find_by_path(&["sat", "pay"]), None);
+            assert!(tree.find_by_path(&["sat", "obc", "ping"]).is_some());
+            assert_eq!(tree.subtree_count(&tree.root), 4);
+
+            tree.remove(tree.root);
+            assert_eq!(tree.subtree_count(&tree.root), 0);
+            assert_eq!(tree.find_by_path(&[]), Some(tree.root));
+        }
+
+        #[test]
+        fn draw() {
+            let mut tree = Tree::new();
+            let sat = tree.insert_at_path(&[], Node::new("sat", "", 0));
+            tree.insert_at_path(&["sat", "obc"], Node::new("ping", "", 0));
+            tree.insert_at_path(&["sat"], Node::new("pay", "", 0));
+            tree.insert_at_path(&[], Node::new("gs", "", 0));
+
+            assert_eq!(
+                tree.draw(),
+                ".\n├── sat\n│   ├── obc\n│   │   └── ping\n│   └── pay\n└── gs\n"
+            );
+            assert_eq!(
+                tree.draw_with(sat, |node, branch| format!("{}{}", node.name, if branch { "/" } else { "" })),
+                "├── obc/\n│   └── ping\n└── pay\n"
+            );
+        }
+
+        #[test]
+        fn into_owned() {
+            let doc = "sat:\n- obc:\n    aliases: [o]\n    children:\n    - set_freq <hz:int>: 'Set the frequency'\ngs: 'Ground station'";
+            let owned: Tree<'static> = {
+                let yaml = yaml_rust::YamlLoader::load_from_str(doc).unwrap();
+                crate::yaml::to_tree(&yaml[0]).unwrap().into_owned()
+            };
+
+            let yaml = yaml_rust::YamlLoader::load_from_str(doc).unwrap();
+            let borrowed = crate::yaml::to_tree(&yaml[0]).unwrap();
+            assert_eq!(owned, borrowed);
+            assert!(matches!(borrowed.arena[borrowed.root].get().name, Cow::Borrowed(_)));
+
+            let set_freq = owned.find_by_path(&["sat", "o", "set_freq"]).unwrap();
+            let node = Node::from_id(&set_freq, &owned.arena);
+            assert!(matches!(node.name, Cow::Owned(_)));
+            assert_eq!(node.explanation.as_deref(), Some("Set the frequency"));
+            assert_eq!(node.arg, Some(ArgSpec { name: "hz".into(), kind: ArgKind::Int }));
+            assert_eq!(owned.path_of(set_freq), vec!["sat", "obc", "set_freq"]);
+        }
+
+        #[test]
+        fn different_depths() {
+            let tree1 = generate_tree(vec![("node", "exp", 0)]);
+            let tree2 = generate_tree(vec![("node", "exp", 1)]);
+
+            assert_ne!(tree1, tree2);
+        }
+    }
+
+    mod yaml {
+        use super::*;
+        use crate::yaml::to_tree;
+        use yaml_rust::YamlLoader;
+        
+        pub(super) const YAMLDOC: &str =
+        "
+        node1:
+        - subnode1:
+          - subsubnode1:
+            'subsubnode1 explanation'
+          - subsubnode2:
+            'subsubnode2 explanation'
+        - subnode2:
+            'subnode2 explanation'
+        node2:
+          'node2 explanation'
+        node3:
+          - subnode1:
+            'subnode1 explanation'
+        ";
+
+
+        #[test]
+        fn tree() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            let nodes = vec![
+                Node::new(ROOT_NAME, "", 0),
+                Node::new("node1", "", 1),
+                Node::new("subnode1", "", 2),
                 Node::new("subsubnode1", "subsubnode1 explanation", 3),
                 Node::new("subsubnode2", "subsubnode2 explanation", 3),
                 Node::new("subnode2", "subnode2 explanation", 2),
@@ -371,5 +1849,794 @@ mod tests {
                 );
             }            
         }
+
+        #[test]
+        fn iter() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            let nodes = vec![
+                Node::new(ROOT_NAME, "", 0),
+                Node::new("node1", "", 1),
+                Node::new("subnode1", "", 2),
+                Node::new("subsubnode1", "subsubnode1 explanation", 3),
+                Node::new("subsubnode2", "subsubnode2 explanation", 3),
+                Node::new("subnode2", "subnode2 explanation", 2),
+                Node::new("node2", "node2 explanation", 1),
+                Node::new("node3", "", 1),
+                Node::new("subnode1", "subnode1 explanation", 2),
+            ];
+
+            assert_eq!(tree.iter().collect::<Vec<_>>(), nodes);
+
+            let mut count = 0;
+            for (i, node) in (&tree).into_iter().enumerate() {
+                assert_eq!(node, nodes[i]);
+                count += 1;
+            }
+            assert_eq!(count, nodes.len());
+
+            // Same order as the Debug output walks
+            let mut names = Vec::new();
+            for node in &tree {
+                names.push(node.name.into_owned());
+            }
+            assert_eq!(
+                names,
+                [ROOT_NAME, "node1", "subnode1", "subsubnode1", "subsubnode2", "subnode2", "node2", "node3", "subnode1"]
+            );
+        }
+
+        #[test]
+        fn ancestors_and_descendants() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let subsubnode2 = tree.find_by_path(&["node1", "subnode1", "subsubnode2"]).unwrap();
+            let node1 = tree.find_by_path(&["node1"]).unwrap();
+
+            let raw: Vec<Node> = subsubnode2
+                .ancestors(&tree.arena)
+                .map(|node| Node::from_id(&node, &tree.arena))
+                .collect();
+            assert_eq!(tree.ancestors(subsubnode2).collect::<Vec<_>>(), raw);
+            let names: Vec<String> = tree.ancestors(subsubnode2).map(|node| node.name.to_string()).collect();
+            assert_eq!(names, vec!["subsubnode2", "subnode1", "node1", ROOT_NAME]);
+
+            let raw: Vec<Node> = node1
+                .descendants(&tree.arena)
+                .map(|node| Node::from_id(&node, &tree.arena))
+                .collect();
+            assert_eq!(tree.descendants(node1).collect::<Vec<_>>(), raw);
+            assert_eq!(tree.descendants(node1).count(), 5);
+            assert_eq!(tree.descendants(tree.root).collect::<Vec<_>>(), tree.iter().collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn clone() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let mut copy = tree.clone();
+            assert!(copy == tree);
+
+            let node1 = copy.find_by_path(&["node1"]).unwrap();
+            copy.arena[node1].get_mut().explanation = Some("changed".into());
+            copy.remove(copy.find_by_path(&["node3"]).unwrap());
+            copy.insert_at_path(&["node2"], Node::new("new", "", 0));
+
+            assert_eq!(tree.arena[node1].get().explanation, None);
+            assert!(tree.find_by_path(&["node3", "subnode1"]).is_some());
+            assert!(tree.find_by_path(&["node2", "new"]).is_none());
+            assert_eq!(tree.subtree_count(&tree.root), 8);
+            assert_eq!(copy.subtree_count(&copy.root), 7);
+            assert!(!copy.owns(tree.node_ref(node1)));
+        }
+
+        #[test]
+        fn snapshot() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let snapshot = tree.snapshot();
+
+            assert_eq!(snapshot.children.len(), 3);
+            assert_eq!(snapshot.children[0].children[0].node.name, "subnode1");
+            assert!(Tree::from(snapshot) == tree);
+        }
+
+        #[cfg(feature = "serde")]
+        #[test]
+        fn serde() {
+            let yaml = YamlLoader::load_from_str("sat:\n- obc:\n  - set_freq <hz:int>: 'Set it'\n  - reboot:\n      hidden: true\n      timeout: 2").unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            let json = serde_json::to_string(&tree.snapshot()).unwrap();
+            let loaded: Snapshot = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded, tree.snapshot());
+
+            let loaded = Tree::from(loaded);
+            let set_freq = loaded.find_by_path(&["sat", "obc", "set_freq"]).unwrap();
+            assert_eq!(loaded.arena[set_freq].get().arg, Some(ArgSpec { name: "hz".into(), kind: ArgKind::Int }));
+            let reboot = loaded.find_by_path(&["sat", "obc", "reboot"]).unwrap();
+            assert!(loaded.arena[reboot].get().hidden);
+            assert_eq!(loaded.arena[reboot].get().timeout, Some(Duration::from_secs(2)));
+        }
+
+        #[test]
+        fn args() {
+            let yaml = YamlLoader::load_from_str(
+                "
+                gs:
+                - radio:
+                  - set_freq <hz:int>:
+                    'Set the frequency'
+                  - rename <name>
+                "
+            ).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let nodes: Vec<Node> = tree.iter().collect();
+
+            assert_eq!(nodes[3].name, "set_freq");
+            assert_eq!(nodes[3].explanation.as_deref(), Some("Set the frequency"));
+            assert_eq!(nodes[3].arg, Some(ArgSpec { name: "hz".into(), kind: ArgKind::Int }));
+            assert_eq!(nodes[4].name, "rename");
+            assert_eq!(nodes[4].arg, Some(ArgSpec { name: "name".into(), kind: ArgKind::String }));
+        }
+
+        #[test]
+        fn invalid_arg() {
+            let yaml = YamlLoader::load_from_str("gs:\n- set_freq <hz:integer>").unwrap();
+
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::InvalidArgument {
+                    path: "gs[0]".to_string(),
+                    key: "set_freq <hz:integer>".to_string(),
+                }
+            );
+        }
+
+        #[test]
+        fn not_a_map() {
+            let yaml = YamlLoader::load_from_str("- sat\n- gs").unwrap();
+
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::UnexpectedType {
+                    path: String::new(),
+                    expected: "a map of commands",
+                    found: "list",
+                }
+            );
+        }
+
+        #[test]
+        fn unexpected_elem() {
+            let yaml = YamlLoader::load_from_str(
+                "
+                sat:
+                - obc:
+                  - ping
+                  - 42
+                "
+            ).unwrap();
+
+            let err = yaml::to_tree(&yaml[0]).unwrap_err();
+            assert_eq!(
+                err,
+                TranslatorError::UnexpectedType {
+                    path: "sat[0]/obc[1]".to_string(),
+                    expected: "a command name or a map",
+                    found: "integer",
+                }
+            );
+            assert_eq!(
+                err.to_string(),
+                "Expected a command name or a map at sat[0]/obc[1], found integer"
+            );
+        }
+
+        #[test]
+        fn unexpected_value() {
+            let yaml = YamlLoader::load_from_str("gs:\n  radio: ping").unwrap();
+
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::UnexpectedType {
+                    path: "gs".to_string(),
+                    expected: "an explanation or a list of commands",
+                    found: "map",
+                }
+            );
+        }
+
+        #[test]
+        fn bare_key() {
+            let yaml = YamlLoader::load_from_str("gs:\nsat: 'Satellite'").unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            assert_eq!(subtree_count(&tree.root, &tree.arena), 2);
+        }
+
+        #[test]
+        fn aliases() {
+            let yaml = YamlLoader::load_from_str(
+                "
+                gs:
+                - sys:
+                    aliases: [system, s]
+                    children:
+                    - config:
+                        aliases: [cfg]
+                "
+            ).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            let sys = tree.find_by_path(&["gs", "sys"]).unwrap();
+            let config = tree.find_by_path(&["gs", "sys", "config"]).unwrap();
+            assert_eq!(Node::from_id(&sys, &tree.arena).aliases, vec!["system", "s"]);
+            assert_eq!(Node::from_id(&config, &tree.arena).names().collect::<Vec<_>>(), vec!["config", "cfg"]);
+            assert_eq!(tree.find_by_path(&["gs", "s", "cfg"]), Some(config));
+            assert_eq!(tree.subtree_count(&tree.root), 3);
+        }
+
+        #[test]
+        fn invalid_aliases() {
+            let yaml = YamlLoader::load_from_str("gs:\n  aliases: ground").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::UnexpectedType {
+                    path: "gs/aliases".to_string(),
+                    expected: "a list of aliases",
+                    found: "string",
+                }
+            );
+
+            let yaml = YamlLoader::load_from_str("gs:\n  aliases: [g, 5]").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::UnexpectedType {
+                    path: "gs/aliases[1]".to_string(),
+                    expected: "an alias",
+                    found: "integer",
+                }
+            );
+        }
+
+        #[test]
+        fn timeouts() {
+            let yaml = YamlLoader::load_from_str("sat:\n  timeout: 5\n  children:\n  - obc:\n      timeout: 0.5\n      children: [ping]\n  - pay").unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let timeout = |path: &[&str]| Node::from_id(&tree.find_by_path(path).unwrap(), &tree.arena).timeout;
+
+            assert_eq!(timeout(&["sat"]), Some(Duration::from_secs(5)));
+            assert_eq!(timeout(&["sat", "obc"]), Some(Duration::from_millis(500)));
+            assert_eq!(timeout(&["sat", "obc", "ping"]), None);
+            assert_eq!(timeout(&["sat", "pay"]), None);
+            assert_eq!(tree.subtree_count(&tree.root), 4);
+
+            for (invalid, found) in [("sat:\n  timeout: -1", "integer"), ("sat:\n  timeout: soon", "string")] {
+                let yaml = YamlLoader::load_from_str(invalid).unwrap();
+                assert_eq!(
+                    yaml::to_tree(&yaml[0]).unwrap_err(),
+                    TranslatorError::UnexpectedType {
+                        path: "sat/timeout".to_string(),
+                        expected: "a timeout in seconds",
+                        found,
+                    }
+                );
+            }
+        }
+
+        #[test]
+        fn option_names_as_commands() {
+            let yaml = YamlLoader::load_from_str(
+                "hidden: 'Not an option here'\nsat:\n- timeout: 'Set the timeout'\n- confirm\n- aliases:\n  - list\n- obc:\n    timeout: 2\n    children: [timeout]"
+            ).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let node = |path: &[&str]| Node::from_id(&tree.find_by_path(path).unwrap(), &tree.arena);
+
+            assert_eq!(node(&["hidden"]).explanation.as_deref(), Some("Not an option here"));
+            assert!(!node(&["hidden"]).hidden);
+            assert_eq!(node(&["sat", "timeout"]).explanation.as_deref(), Some("Set the timeout"));
+            assert_eq!(node(&["sat"]).timeout, None);
+            assert!(!node(&["sat", "confirm"]).confirm);
+            assert!(tree.find_by_path(&["sat", "aliases", "list"]).is_some());
+            assert_eq!(node(&["sat", "obc"]).timeout, Some(Duration::from_secs(2)));
+            assert_eq!(node(&["sat", "obc", "timeout"]).timeout, None);
+        }
+
+        #[test]
+        fn duplicates() {
+            let yaml = YamlLoader::load_from_str("sat:\n- obc:\n  - ping\n  - set\n  - ping: 'Again'").unwrap();
+            let err = yaml::to_tree(&yaml[0]).unwrap_err();
+            assert_eq!(
+                err,
+                TranslatorError::DuplicateCommand {
+                    path: "sat/obc".to_string(),
+                    name: "ping".to_string(),
+                }
+            );
+            assert_eq!(err.to_string(), "Duplicate command 'ping' at sat/obc");
+
+            // An alias can't take a sibling's name either
+            let yaml = YamlLoader::load_from_str("sat:\n- ping\n- reboot:\n    aliases: [ping]").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err().to_string(),
+                "Duplicate command 'ping' at sat"
+            );
+
+            // The same name under different parents is fine
+            let yaml = YamlLoader::load_from_str("sat:\n- ping\ngs:\n- ping").unwrap();
+            assert!(yaml::to_tree(&yaml[0]).is_ok());
+        }
+
+        #[test]
+        fn too_deep() {
+            let yaml = YamlLoader::load_from_str("sat:\n- obc:\n  - mem:\n    - dump").unwrap();
+            assert!(crate::yaml::to_tree_max_depth(&yaml[0], 4).is_ok());
+
+            let err = crate::yaml::to_tree_max_depth(&yaml[0], 3).unwrap_err();
+            assert_eq!(
+                err,
+                TranslatorError::TooDeep { path: "sat[0]/obc[0]/mem".to_string(), max: 3 }
+            );
+            assert_eq!(err.to_string(), "Commands nested deeper than 3 levels at sat[0]/obc[0]/mem");
+
+            // One level past the default, as a chain of single-command maps
+            let mut doc = String::new();
+            for level in 0..=MAX_DEPTH {
+                doc.push_str(&format!("{}- c{}:\n", "  ".repeat(level), level));
+            }
+            let yaml = YamlLoader::load_from_str(&doc[2..].replace("\n  ", "\n")).unwrap();
+            assert!(matches!(yaml::to_tree(&yaml[0]), Err(TranslatorError::TooDeep { max: MAX_DEPTH, .. })));
+        }
+
+        #[test]
+        fn described() {
+            let yaml = YamlLoader::load_from_str(
+                "
+                sat:
+                  description: 'Satellite'
+                  children:
+                  - obc:
+                      description: 'On-board computer'
+                      aliases: [o]
+                      children:
+                      - ping: 'Ping the obc'
+                  - pay:
+                      children: [take_pic]
+                  - adcs:
+                      description: 'Attitude control'
+                gs:
+                - ping
+                "
+            ).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+            let explanation = |path: &[&str]| Node::from_id(&tree.find_by_path(path).unwrap(), &tree.arena).explanation;
+
+            assert_eq!(explanation(&["sat"]).as_deref(), Some("Satellite"));
+            assert_eq!(explanation(&["sat", "o"]).as_deref(), Some("On-board computer"));
+            assert_eq!(explanation(&["sat", "obc", "ping"]).as_deref(), Some("Ping the obc"));
+            assert_eq!(explanation(&["sat", "pay"]), None);
+            assert_eq!(explanation(&["sat", "adcs"]).as_deref(), Some("Attitude control"));
+            assert!(tree.find_by_path(&["sat", "pay", "take_pic"]).is_some());
+            assert_eq!(tree.subtree_count(&tree.root), 8);
+
+            let yaml = YamlLoader::load_from_str("sat:\n  description: 'Satellite'\n  children: ping").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::UnexpectedType {
+                    path: "sat/children".to_string(),
+                    expected: "a list of commands",
+                    found: "string",
+                }
+            );
+
+            let yaml = YamlLoader::load_from_str("sat:\n  children:\n  - 42").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err().to_string(),
+                "Expected a command name or a map at sat/children[0], found integer"
+            );
+        }
+
+        #[test]
+        fn hidden() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n- dump_mem:\n    hidden: true\n- ping\n- debug:\n    hidden: false\n    children: [hidden_cmd]"
+            ).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            let hidden: Vec<bool> = tree.iter().skip(2).map(|node| node.hidden).collect();
+            assert_eq!(hidden, vec![true, false, false, false]);
+            assert_eq!(tree.draw(), ".\n└── sat\n    ├── ping\n    └── debug\n        └── hidden_cmd\n");
+
+            let yaml = YamlLoader::load_from_str("sat:\n  hidden: 'yes'").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err(),
+                TranslatorError::UnexpectedType {
+                    path: "sat/hidden".to_string(),
+                    expected: "true or false",
+                    found: "string",
+                }
+            );
+        }
+
+        #[test]
+        fn confirm() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n- obc:\n    confirm: true\n    children: [reboot]\n- ping\n- wipe:\n    confirm: false"
+            ).unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            let confirm: Vec<bool> = tree.iter().skip(2).map(|node| node.confirm).collect();
+            assert_eq!(confirm, vec![true, false, false, false]);
+
+            let yaml = YamlLoader::load_from_str("sat:\n  confirm: 1").unwrap();
+            assert_eq!(
+                yaml::to_tree(&yaml[0]).unwrap_err().to_string(),
+                "Expected true or false at sat/confirm, found integer"
+            );
+        }
+
+        #[test]
+        fn profiles() {
+            // Document markers only count at the start of a line
+            let docs = YamlLoader::load_from_str(
+                "profile: spacecraft_a\nsat:\n- ping\n---\nprofile: spacecraft_b\nsat:\n- ping\n- reboot\n"
+            ).unwrap();
+
+            let tree = crate::yaml::profile_to_tree(&docs, "spacecraft_b").unwrap();
+            assert!(tree.find_by_path(&["sat", "reboot"]).is_some());
+            assert_eq!(tree.find_by_path(&["profile"]), None);
+            assert_eq!(tree.subtree_count(&tree.root), 3);
+
+            let tree = crate::yaml::profile_to_tree(&docs, "1").unwrap();
+            assert!(tree.find_by_path(&["sat", "reboot"]).is_some());
+            let tree = crate::yaml::profile_to_tree(&docs, "0").unwrap();
+            assert_eq!(tree.find_by_path(&["sat", "reboot"]), None);
+
+            assert_eq!(
+                crate::yaml::profile_to_tree(&docs, "spacecraft_c").unwrap_err(),
+                TranslatorError::NoSuchProfile("spacecraft_c".to_string())
+            );
+            assert!(crate::yaml::profile_to_tree(&docs, "2").is_err());
+        }
+
+        #[test]
+        fn payloads() {
+            #[derive(Debug, Clone, Default, PartialEq)]
+            struct Meta {
+                opcode: u8,
+                depth: usize,
+            }
+
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let mut opcode = 0;
+            let tree = crate::yaml::to_tree_with(&yaml[0], |path, _| {
+                opcode += 1;
+                Meta { opcode, depth: path.len() }
+            }).unwrap();
+
+            let leaf = tree.find_by_path(&["node1", "subnode1", "subsubnode2"]).unwrap();
+            let node = Node::from_id(&leaf, &tree.arena);
+            assert_eq!(node.payload, Meta { opcode: 5, depth: 3 });
+            assert_eq!(node.explanation.as_deref(), Some("subsubnode2 explanation"));
+            assert_eq!(tree.arena[tree.root].get().payload, Meta { opcode: 1, depth: 0 });
+            assert_eq!(tree.subtree_count(&tree.root), 8);
+
+            let names: Vec<String> = tree.iter().filter(|node| node.payload.depth == 2).map(|node| node.name.into_owned()).collect();
+            assert_eq!(names, vec!["subnode1", "subnode2", "subnode1"]);
+        }
+
+        #[test]
+        fn round_trip() {
+            let docs = [
+                YAMLDOC,
+                "gs:\n  aliases: [g]\n  children:\n  - sys:\n      aliases: [s]\n      children:\n      - config:\n          aliases: [cfg]\n          hidden: true",
+                "gs:\n- radio:\n  - set_freq <hz:int>: 'Set the frequency'\n  - mode <name>\n  - ping\nsat:",
+                "sat:\n  timeout: 5\n  children:\n  - obc:\n      timeout: 0.25\n      children: [ping]",
+                "sat:\n- obc:\n  - reboot: 'Reboot the obc'\n  - wipe:\n      confirm: true",
+                "sat:\n  description: 'Satellite'\n  children:\n  - obc:\n      description: 'OBC'\n      children: [ping]",
+            ];
+
+            for doc in docs {
+                let yaml = YamlLoader::load_from_str(doc).unwrap();
+                let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+                let dumped = crate::yaml::from_tree(&tree);
+                let yaml = YamlLoader::load_from_str(&dumped).unwrap();
+                let reparsed = yaml::to_tree(&yaml[0]).unwrap();
+
+                assert_eq!(tree, reparsed);
+                assert_eq!(tree.iter().collect::<Vec<_>>(), reparsed.iter().collect::<Vec<_>>());
+            }
+        }
+
+        #[test]
+        fn emitted() {
+            let yaml = YamlLoader::load_from_str("gs:\n- ping: 'Ping'\n- set <v:float>\nsat:").unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            assert_eq!(
+                crate::yaml::from_tree(&tree),
+                "---\ngs:\n  - ping: Ping\n  - \"set <v:float>\": ~\nsat: ~\n"
+            );
+
+            let yaml = YamlLoader::load_from_str("gs:\n  description: 'Ground station'\n  children: [ping]").unwrap();
+            let tree = yaml::to_tree(&yaml[0]).unwrap();
+
+            assert_eq!(
+                crate::yaml::from_tree(&tree),
+                "---\ngs:\n  description: Ground station\n  children:\n    - ping: ~\n"
+            );
+        }
+    }
+
+    mod json {
+        use super::*;
+        use yaml_rust::YamlLoader;
+
+        const YAMLDOC: &str =
+        "
+        sat:
+        - obc:
+            aliases: [cmd]
+            children:
+            - ping:
+              'Ping the obc'
+            - set
+        - pay
+        - reboot:
+            confirm: true
+        gs:
+          'Ground station'
+        ";
+
+        const JSONDOC: &str = r#"
+        {
+            "sat": [
+                { "obc": {
+                    "aliases": ["cmd"],
+                    "children": [{ "ping": "Ping the obc" }, "set"]
+                } },
+                "pay",
+                { "reboot": { "confirm": true } }
+            ],
+            "gs": "Ground station"
+        }
+        "#;
+
+        #[test]
+        fn same_as_yaml() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let json: serde_json::Value = serde_json::from_str(JSONDOC).unwrap();
+
+            let yaml_tree = crate::yaml::to_tree(&yaml[0]).unwrap();
+            let json_tree = crate::json::to_tree(&json).unwrap();
+
+            assert_eq!(yaml_tree.arena.len(), json_tree.arena.len());
+            assert_eq!(yaml_tree, json_tree);
+            assert!(Node::from_id(&json_tree.find_by_path(&["sat", "reboot"]).unwrap(), &json_tree.arena).confirm);
+        }
+
+        #[test]
+        fn timeouts() {
+            let json: serde_json::Value = serde_json::from_str(r#"{"sat": {"timeout": 2, "children": [{"obc": {"timeout": 0.5, "children": ["ping"]}}]}}"#).unwrap();
+            let tree = crate::json::to_tree(&json).unwrap();
+            let timeout = |path: &[&str]| Node::from_id(&tree.find_by_path(path).unwrap(), &tree.arena).timeout;
+
+            assert_eq!(timeout(&["sat"]), Some(Duration::from_secs(2)));
+            assert_eq!(timeout(&["sat", "obc"]), Some(Duration::from_millis(500)));
+        }
+
+        #[test]
+        fn not_an_object() {
+            let json: serde_json::Value = serde_json::from_str("[1, 2]").unwrap();
+
+            assert_eq!(
+                crate::json::to_tree(&json).unwrap_err().to_string(),
+                "Expected a map of commands at top level, found list"
+            );
+        }
+
+        #[test]
+        fn long_form() {
+            let yaml = YamlLoader::load_from_str(
+                "sat:\n  description: 'Satellite'\n  children:\n  - obc: [ping]\n  - pay"
+            ).unwrap();
+            let json: serde_json::Value = serde_json::from_str(
+                r#"{"sat": {"description": "Satellite", "children": [{"obc": ["ping"]}, "pay"]}}"#
+            ).unwrap();
+
+            assert_eq!(crate::yaml::to_tree(&yaml[0]).unwrap(), crate::json::to_tree(&json).unwrap());
+        }
+
+        #[test]
+        fn errors() {
+            let error = |doc: &str| {
+                let json: serde_json::Value = serde_json::from_str(doc).unwrap();
+                crate::json::to_tree(&json).unwrap_err().to_string()
+            };
+
+            assert_eq!(error(r#"{"sat": 1}"#), "Expected an explanation or a list of commands at sat, found integer");
+            assert_eq!(error(r#"{"sat": [true]}"#), "Expected a command name or an object at sat[0], found boolean");
+            assert_eq!(error(r#"{"sat": {"hidden": "yes"}}"#), "Expected true or false at sat/hidden, found string");
+            assert_eq!(error(r#"{"sat": {"confirm": 1}}"#), "Expected true or false at sat/confirm, found integer");
+            assert_eq!(error(r#"{"sat": {"timeout": -1.5}}"#), "Expected a timeout in seconds at sat/timeout, found float");
+            assert_eq!(error(r#"{"sat": {"aliases": ["s", 2]}}"#), "Expected an alias at sat/aliases[1], found integer");
+            assert_eq!(error(r#"{"sat": [{"hidden": true}]}"#), "Expected an explanation or a list of commands at sat[0]/hidden, found boolean");
+            assert_eq!(error(r#"{"sat": {"children": "obc"}}"#), "Expected a list of commands at sat/children, found string");
+            assert_eq!(error(r#"{"sat": ["set <hz:integer>"]}"#), "Invalid argument declaration 'set <hz:integer>' at sat[0]");
+            assert_eq!(error(r#"{"sat": [{"obc": ["ping", "set", {"ping": "Again"}]}]}"#), "Duplicate command 'ping' at sat/obc");
+        }
+
+        #[test]
+        fn too_deep() {
+            let json: serde_json::Value = serde_json::from_str(r#"{"sat": [{"obc": [{"mem": ["dump"]}]}]}"#).unwrap();
+            assert!(crate::json::to_tree_max_depth(&json, 4).is_ok());
+            assert_eq!(
+                crate::json::to_tree_max_depth(&json, 3).unwrap_err(),
+                TranslatorError::TooDeep { path: "sat[0]/obc[0]/mem".to_string(), max: 3 }
+            );
+
+            // Built directly, since serde_json limits how deep it parses
+            let mut json = serde_json::Value::Null;
+            for level in (0..=MAX_DEPTH).rev() {
+                json = serde_json::json!({ format!("c{}", level): [json] });
+            }
+            assert!(matches!(crate::json::to_tree(&json), Err(TranslatorError::TooDeep { max: MAX_DEPTH, .. })));
+        }
     }
-}
\ No newline at end of file
+
+    mod closest {
+        use super::*;
+
+        #[test]
+        fn distance() {
+            assert_eq!(levenshtein("", ""), 0);
+            assert_eq!(levenshtein("ping", "ping"), 0);
+            assert_eq!(levenshtein("pnig", "ping"), 2);
+            assert_eq!(levenshtein("kitten", "sitting"), 3);
+            assert_eq!(levenshtein("", "obc"), 3);
+        }
+
+        #[test]
+        fn suggestion() {
+            let candidates = ["ping", "set_freq", "get_status"];
+
+            assert_eq!(closest("pnig", &candidates), Some("ping"));
+            assert_eq!(closest("set_frq", &candidates), Some("set_freq"));
+            assert_eq!(closest("ping", &candidates), Some("ping"));
+            assert_eq!(closest("reboot", &candidates), None);
+            assert_eq!(closest("pnig", &[]), None);
+        }
+
+        #[test]
+        fn tie_prefers_shorter() {
+            assert_eq!(closest("pay", &["pays", "pa"]), Some("pa"));
+            assert_eq!(closest("pay", &["pa", "pays"]), Some("pa"));
+        }
+    }
+
+    mod toml {
+        use super::*;
+        use yaml_rust::YamlLoader;
+
+        // TOML wants plain keys before any table headers, so gs comes first
+        const YAMLDOC: &str =
+        "
+        gs:
+          'Ground station'
+        sat:
+        - obc:
+            aliases: [cmd]
+            children:
+            - ping:
+              'Ping the obc'
+            - set
+        - pay:
+          - take_pic:
+            'Take a picture'
+        - reboot:
+            description: 'Reboot the satellite'
+            confirm: true
+        ";
+
+        const TOMLDOC: &str = r#"
+        gs = "Ground station"
+
+        [[sat]]
+        [sat.obc]
+        aliases = ["cmd"]
+        children = [{ ping = "Ping the obc" }, "set"]
+
+        [[sat]]
+        [[sat.pay]]
+        take_pic = "Take a picture"
+
+        [[sat]]
+        [sat.reboot]
+        description = "Reboot the satellite"
+        confirm = true
+        "#;
+
+        #[test]
+        fn same_as_yaml() {
+            let yaml = YamlLoader::load_from_str(YAMLDOC).unwrap();
+            let toml: ::toml::Value = TOMLDOC.parse().unwrap();
+
+            let yaml_tree = crate::yaml::to_tree(&yaml[0]).unwrap();
+            let toml_tree = crate::toml::to_tree(&toml).unwrap();
+
+            assert_eq!(yaml_tree.arena.len(), toml_tree.arena.len());
+            assert_eq!(yaml_tree, toml_tree);
+        }
+
+        #[test]
+        fn shape() {
+            let toml: ::toml::Value = "[[sat]]\n[[sat.obc]]\nping = \"Ping the obc\"".parse().unwrap();
+            let tree = crate::toml::to_tree(&toml).unwrap();
+            let node = |path: &[&str]| Node::from_id(&tree.find_by_path(path).unwrap(), &tree.arena);
+
+            assert_eq!(node(&["sat"]), Node::new("sat", "", Depth::Some(1)));
+            assert_eq!(node(&["sat", "obc"]), Node::new("obc", "", Depth::Some(2)));
+            assert_eq!(node(&["sat", "obc", "ping"]), Node::new("ping", "Ping the obc", Depth::Some(3)));
+            assert_eq!(tree.subtree_count(&tree.root), 3);
+        }
+
+        #[test]
+        fn inline_arrays() {
+            let toml: ::toml::Value = r#"sat = [{ obc = ["ping", "set"] }, "pay"]"#.parse().unwrap();
+            let tree = crate::toml::to_tree(&toml).unwrap();
+
+            assert!(tree.find_by_path(&["sat", "obc", "set"]).is_some());
+            assert!(tree.find_by_path(&["sat", "pay"]).is_some());
+            assert_eq!(tree.subtree_count(&tree.root), 5);
+        }
+
+        #[test]
+        fn not_a_table() {
+            assert_eq!(
+                crate::toml::to_tree(&::toml::Value::Integer(1)).unwrap_err().to_string(),
+                "Expected a table of commands at top level, found integer"
+            );
+        }
+
+        #[test]
+        fn errors() {
+            let error = |doc: &str| {
+                let toml: ::toml::Value = doc.parse().unwrap();
+                crate::toml::to_tree(&toml).unwrap_err().to_string()
+            };
+
+            assert_eq!(error("sat = 1"), "Expected an explanation or a list of commands at sat, found integer");
+            assert_eq!(error("sat = [true]"), "Expected a command name or a table at sat[0], found boolean");
+            assert_eq!(error("[sat]\nhidden = 'yes'"), "Expected true or false at sat/hidden, found string");
+            assert_eq!(error("[sat]\nconfirm = 1"), "Expected true or false at sat/confirm, found integer");
+            assert_eq!(error("[sat]\ntimeout = -1"), "Expected a timeout in seconds at sat/timeout, found integer");
+            assert_eq!(error("[sat]\naliases = ['s', 2]"), "Expected an alias at sat/aliases[1], found integer");
+            assert_eq!(error("[sat]\nping = 'Ping'"), "Expected an explanation or a list of commands at sat, found table");
+            assert_eq!(error("[sat]\ndescription = 1"), "Expected an explanation at sat/description, found integer");
+            assert_eq!(error("[[sat]]\nhidden = true"), "Expected an explanation or a list of commands at sat[0]/hidden, found boolean");
+            assert_eq!(error("sat = ['set <hz:integer>']"), "Invalid argument declaration 'set <hz:integer>' at sat[0]");
+            assert_eq!(error("sat = [{ obc = ['ping', 'set', { ping = 'Again' }] }]"), "Duplicate command 'ping' at sat/obc");
+        }
+
+        #[test]
+        fn too_deep() {
+            let toml: ::toml::Value = "sat = [{ obc = [{ mem = ['dump'] }] }]".parse().unwrap();
+            assert!(crate::toml::to_tree_max_depth(&toml, 4).is_ok());
+            assert_eq!(
+                crate::toml::to_tree_max_depth(&toml, 3).unwrap_err(),
+                TranslatorError::TooDeep { path: "sat[0]/obc[0]/mem".to_string(), max: 3 }
+            );
+
+            let mut toml = ::toml::Value::String("dump".to_string());
+            for level in (0..=MAX_DEPTH).rev() {
+                let mut table = ::toml::Table::new();
+                table.insert(format!("c{}", level), ::toml::Value::Array(vec![toml]));
+                toml = ::toml::Value::Table(table);
+            }
+            assert!(matches!(crate::toml::to_tree(&toml), Err(TranslatorError::TooDeep { max: MAX_DEPTH, .. })));
+        }
+    }
+}